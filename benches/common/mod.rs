@@ -0,0 +1,33 @@
+//! RNG shared by the host benchmarks and the on-target cycle harness.
+
+use rand_core::{CryptoRng, Error, RngCore};
+
+/// Deterministic RNG so every run measures the same delays.
+pub struct BenchRng(pub u64);
+
+impl RngCore for BenchRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (u64::from(self.next_u32()) << 32) | u64::from(self.next_u32())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for byte in dest {
+            *byte = self.next_u32() as u8;
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for BenchRng {}
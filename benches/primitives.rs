@@ -0,0 +1,71 @@
+//! Measures the cost of the core primitives on the host backend. Run with:
+//!
+//! ```text
+//! cargo bench --target x86_64-unknown-linux-gnu
+//! ```
+//!
+//! Host timings only show the relative cost of the primitives. For cycle counts on a target, use
+//! `examples/dwt_cycles.rs`.
+
+mod common;
+
+use common::BenchRng;
+use fault_injection_protection_arm::{FaultInjectionPrevention, SecureBool};
+use std::hint::black_box;
+use std::time::Instant;
+
+const ITERATIONS: u32 = 100_000;
+
+/// Runs `op` for `ITERATIONS` iterations and prints the average time per call.
+fn bench(name: &str, mut op: impl FnMut(&mut BenchRng)) {
+    let mut rng = BenchRng(1);
+    let start = Instant::now();
+
+    for _ in 0..ITERATIONS {
+        op(&mut rng);
+    }
+
+    let elapsed = start.elapsed();
+    println!(
+        "{name:<20} {:>8.1} ns/call",
+        elapsed.as_nanos() as f64 / f64::from(ITERATIONS)
+    );
+}
+
+fn main() {
+    let fip = FaultInjectionPrevention::new();
+    let value: u32 = 0x1234_5678;
+    let mut dst: u32 = 0;
+
+    bench("critical_if", |rng| {
+        fip.critical_if(
+            || SecureBool::from(black_box(value) != 0),
+            || (),
+            || (),
+            rng,
+        )
+    });
+    bench("critical_read", |rng| {
+        black_box(fip.critical_read(&value, rng));
+    });
+    bench("critical_read_fast", |_| {
+        black_box(fip.critical_read_fast(&value));
+    });
+    bench("critical_write", |rng| {
+        fip.critical_write(
+            &mut dst,
+            value,
+            // SAFETY: dst is a valid, properly aligned u32.
+            |dst, src| unsafe { core::ptr::write_volatile(dst, src) },
+            rng,
+        )
+    });
+    bench("stack_canary", |rng| {
+        fip.stack_canary(
+            || {
+                black_box(value);
+            },
+            rng,
+        )
+    });
+}
@@ -0,0 +1,121 @@
+//! Measures the cycle cost of the core primitives on a target with the DWT cycle counter. The
+//! runtime comes from `cortex-m-rt`, an ARM-only dev-dependency, whose `link.x` includes the
+//! `memory.x` at the crate root. `memory.x` describes an STM32F407, so change it to match the
+//! target board first. Run with:
+//!
+//! ```text
+//! cargo run --release --example dwt_cycles
+//! ```
+//!
+//! The runner stops at a breakpoint once every primitive was measured. The average cycles per
+//! call are then in `CYCLES`, in the order of `PRIMITIVES`, e.g. with `print CYCLES` in gdb.
+//!
+//! On the host, use `benches/primitives.rs` instead.
+
+#![cfg_attr(target_arch = "arm", no_std)]
+#![cfg_attr(target_arch = "arm", no_main)]
+
+#[cfg(target_arch = "arm")]
+#[path = "../benches/common/mod.rs"]
+mod common;
+
+#[cfg(target_arch = "arm")]
+mod target {
+    use crate::common::BenchRng;
+    use core::hint::black_box;
+    use core::ptr::{addr_of_mut, write_volatile};
+    use cortex_m::peripheral::{Peripherals, DWT};
+    use fault_injection_protection_arm::{FaultInjectionPrevention, SecureBool};
+
+    const ITERATIONS: u32 = 1_000;
+
+    /// Names of the measured primitives, in the order of `CYCLES`.
+    #[no_mangle]
+    static PRIMITIVES: [&str; 5] = [
+        "critical_if",
+        "critical_read",
+        "critical_read_fast",
+        "critical_write",
+        "stack_canary",
+    ];
+
+    /// Average cycles per call of each primitive, read with a debugger.
+    #[no_mangle]
+    static mut CYCLES: [u32; 5] = [0; 5];
+
+    /// Runs `op` for `ITERATIONS` iterations and returns the average cycles per call.
+    fn measure(mut op: impl FnMut(&mut BenchRng)) -> u32 {
+        let mut rng = BenchRng(1);
+        let start = DWT::cycle_count();
+
+        for _ in 0..ITERATIONS {
+            op(&mut rng);
+        }
+
+        DWT::cycle_count().wrapping_sub(start) / ITERATIONS
+    }
+
+    #[cortex_m_rt::entry]
+    fn main() -> ! {
+        // SAFETY: Nothing else uses the peripherals.
+        let mut peripherals = unsafe { Peripherals::steal() };
+        peripherals.DCB.enable_trace();
+        peripherals.DWT.enable_cycle_counter();
+
+        let fip = FaultInjectionPrevention::new();
+        let value: u32 = 0x1234_5678;
+        let mut dst: u32 = 0;
+
+        let cycles = [
+            measure(|rng| {
+                fip.critical_if(
+                    || SecureBool::from(black_box(value) != 0),
+                    || (),
+                    || (),
+                    rng,
+                )
+            }),
+            measure(|rng| {
+                black_box(fip.critical_read(&value, rng));
+            }),
+            measure(|_| {
+                black_box(fip.critical_read_fast(&value));
+            }),
+            measure(|rng| {
+                fip.critical_write(
+                    &mut dst,
+                    value,
+                    // SAFETY: dst is a valid, properly aligned u32.
+                    |dst, src| unsafe { core::ptr::write_volatile(dst, src) },
+                    rng,
+                )
+            }),
+            measure(|rng| {
+                fip.stack_canary(
+                    || {
+                        black_box(value);
+                    },
+                    rng,
+                )
+            }),
+        ];
+
+        // SAFETY: No race conditions because this program is single threaded. CYCLES is a static,
+        // so it's non-null and properly aligned.
+        unsafe {
+            write_volatile(addr_of_mut!(CYCLES), cycles);
+        }
+
+        // Keeps PRIMITIVES from being removed by the linker, so the debugger can read it.
+        black_box(&PRIMITIVES);
+
+        loop {
+            cortex_m::asm::bkpt();
+        }
+    }
+}
+
+#[cfg(not(target_arch = "arm"))]
+fn main() {
+    println!("dwt_cycles measures cycle counts on a target, use benches/primitives.rs on the host");
+}
@@ -0,0 +1,8 @@
+/* Memory layout for `examples/dwt_cycles.rs`, included by the `link.x` of `cortex-m-rt`. This is
+   the layout of an STM32F407, with 1 MiB of flash and the 128 KiB main SRAM. Change it to match
+   the target board before flashing. */
+MEMORY
+{
+  FLASH : ORIGIN = 0x08000000, LENGTH = 1024K
+  RAM   : ORIGIN = 0x20000000, LENGTH = 128K
+}
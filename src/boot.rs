@@ -0,0 +1,149 @@
+//! Fault-injection resistant slot selection for A/B firmware updates.
+
+use crate::{secure_enum, FaultInjectionPrevention, SecureBool};
+use core::hint::black_box;
+use rand_core::CryptoRngCore;
+
+secure_enum! {
+    /// A firmware slot of an A/B update scheme, returned by
+    /// [`FaultInjectionPrevention::critical_select_boot_slot()`].
+    pub enum BootSlot {
+        A,
+        B,
+    }
+}
+
+impl BootSlot {
+    /// Value of the active flag that selects slot A. The flag values are complements of each
+    /// other, so a glitch has to flip every bit of the flag to switch slots.
+    pub const A_FLAG: u32 = 0xA5C3_5A3C;
+
+    /// Value of the active flag that selects slot B.
+    pub const B_FLAG: u32 = !Self::A_FLAG;
+}
+
+impl FaultInjectionPrevention {
+    /// Selects the firmware slot to boot in an A/B update scheme, so a glitch on the selection
+    /// can't boot stale or rolled-back firmware. `active_flag` is redundantly read with
+    /// [`FaultInjectionPrevention::critical_read()`] and must be [`BootSlot::A_FLAG`] or
+    /// [`BootSlot::B_FLAG`]. The active slot is booted if it is valid, and otherwise the other slot
+    /// is booted if it is valid.
+    ///
+    /// Returns the slot to boot. If the active flag is neither flag value, a validity marker is
+    /// [`SecureBool::Error`], neither slot is valid, or the redundant checks disagree, the board
+    /// securely resets itself.
+    #[inline(always)]
+    pub fn critical_select_boot_slot(
+        &self,
+        active_flag: &u32,
+        slot_a_valid: SecureBool,
+        slot_b_valid: SecureBool,
+        rng: &mut impl CryptoRngCore,
+    ) -> BootSlot {
+        let flag = self.critical_read(active_flag, rng).into_inner();
+
+        self.critical_assert(
+            || black_box(flag) == BootSlot::A_FLAG || black_box(flag) == BootSlot::B_FLAG,
+            rng,
+        );
+        self.critical_assert(
+            || {
+                black_box(slot_a_valid) != SecureBool::Error
+                    && black_box(slot_b_valid) != SecureBool::Error
+            },
+            rng,
+        );
+        self.critical_assert(
+            || {
+                black_box(slot_a_valid) == SecureBool::True
+                    || black_box(slot_b_valid) == SecureBool::True
+            },
+            rng,
+        );
+
+        // Boot A if it is active, or if it is the only valid slot.
+        let boot_a = self.critical_bool(
+            || {
+                (black_box(slot_a_valid) == SecureBool::True
+                    && (black_box(flag) == BootSlot::A_FLAG
+                        || black_box(slot_b_valid) == SecureBool::False))
+                    .into()
+            },
+            rng,
+        );
+
+        if black_box(boot_a) == SecureBool::True {
+            // Re-check so a glitch on the branch above can't boot an invalid slot.
+            self.critical_assert(|| black_box(slot_a_valid) == SecureBool::True, rng);
+            BootSlot::A
+        } else {
+            self.critical_assert(|| black_box(slot_b_valid) == SecureBool::True, rng);
+            BootSlot::B
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::mock;
+    use crate::test_util::{resets, TestRng};
+
+    /// Selects the slot to boot, or returns `None` if the device reset.
+    fn select(flag: u32, slot_a_valid: SecureBool, slot_b_valid: SecureBool) -> Option<BootSlot> {
+        let fip = FaultInjectionPrevention::new();
+        let mut slot = None;
+
+        // The slot is only set if the selection returned without resetting.
+        resets(|| {
+            slot = Some(fip.critical_select_boot_slot(
+                &flag,
+                slot_a_valid,
+                slot_b_valid,
+                &mut TestRng(1),
+            ));
+        });
+
+        slot
+    }
+
+    #[test]
+    fn critical_select_boot_slot_boots_active_slot() {
+        use SecureBool::{False, True};
+
+        assert!(select(BootSlot::A_FLAG, True, True) == Some(BootSlot::A));
+        assert!(select(BootSlot::A_FLAG, True, False) == Some(BootSlot::A));
+        assert!(select(BootSlot::B_FLAG, True, True) == Some(BootSlot::B));
+        assert!(select(BootSlot::B_FLAG, False, True) == Some(BootSlot::B));
+    }
+
+    #[test]
+    fn critical_select_boot_slot_falls_back_to_other_valid_slot() {
+        use SecureBool::{False, True};
+
+        assert!(select(BootSlot::A_FLAG, False, True) == Some(BootSlot::B));
+        assert!(select(BootSlot::B_FLAG, True, False) == Some(BootSlot::A));
+    }
+
+    #[test]
+    fn critical_select_boot_slot_resets_when_neither_slot_is_valid() {
+        use SecureBool::{Error, False};
+
+        assert!(select(BootSlot::A_FLAG, False, False).is_none());
+        assert!(select(BootSlot::B_FLAG, False, False).is_none());
+        assert!(select(BootSlot::A_FLAG, Error, False).is_none());
+        assert!(select(BootSlot::B_FLAG, False, Error).is_none());
+    }
+
+    #[test]
+    fn critical_select_boot_slot_resets_on_invalid_or_glitched_flag() {
+        use SecureBool::True;
+
+        assert!(select(0, True, True).is_none());
+        assert!(select(BootSlot::A_FLAG ^ 1, True, True).is_none());
+
+        // The flag changes between the redundant reads.
+        mock::change_source_after(3);
+        assert!(select(BootSlot::A_FLAG, True, True).is_none());
+    }
+}
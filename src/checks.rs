@@ -0,0 +1,1057 @@
+//! Fault-injection resistant checks on values supplied by the caller.
+
+use crate::platform::{Arch, Platform};
+use crate::{FaultInjectionPrevention, SecureBool};
+use core::hint::black_box;
+use core::ptr::{read_volatile, write_volatile};
+use rand_core::CryptoRngCore;
+
+// Size of a TLV header, a type byte followed by a length byte
+const TLV_HEADER_LEN: usize = 2;
+
+// Cycle range of the random delay between button samples in `critical_confirm_presence`
+const PRESENCE_SAMPLE_MIN_CYCLES: u32 = 50_000;
+const PRESENCE_SAMPLE_MAX_CYCLES: u32 = 100_000;
+
+impl FaultInjectionPrevention {
+    /// Verifies that two independent time readings, e.g. SysTick and the RTC, agree to within
+    /// `tolerance`. Relying on a single timer for time-gated security lets an attacker glitch or
+    /// manipulate that one clock, while a divergence between two sources indicates tampering.
+    ///
+    /// Returns [`SecureBool::True`] if `t1` and `t2` differ by at most `tolerance`, and
+    /// [`SecureBool::False`] otherwise. Use
+    /// [`FaultInjectionPrevention::critical_assert_time_agrees()`] to reset on a divergence instead.
+    #[inline(always)]
+    pub fn critical_cross_check_time(
+        &self,
+        t1: u64,
+        t2: u64,
+        tolerance: u64,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        self.critical_bool(
+            || (black_box(black_box(t1).abs_diff(black_box(t2))) <= black_box(tolerance)).into(),
+            rng,
+        )
+    }
+
+    /// Same as [`FaultInjectionPrevention::critical_cross_check_time()`], but securely resets the
+    /// board if the two time readings diverge by more than `tolerance`.
+    #[inline(always)]
+    pub fn critical_assert_time_agrees(
+        &self,
+        t1: u64,
+        t2: u64,
+        tolerance: u64,
+        rng: &mut impl CryptoRngCore,
+    ) {
+        let agrees = self.critical_cross_check_time(t1, t2, tolerance, rng);
+
+        self.critical_assert(|| black_box(agrees) == SecureBool::True, rng);
+    }
+
+    /// Combines the readings of two independent sensors that must agree, e.g. two switches that
+    /// detect whether an enclosure is closed, so glitching a single sensor is insufficient.
+    ///
+    /// Returns [`SecureBool::True`] if both sensors read [`SecureBool::True`], and
+    /// [`SecureBool::False`] if both read [`SecureBool::False`]. If the sensors disagree, or either
+    /// reads [`SecureBool::Error`], the board securely resets itself.
+    #[inline(always)]
+    pub fn critical_sensor_agree(
+        &self,
+        a: SecureBool,
+        b: SecureBool,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        self.critical_assert(
+            || {
+                black_box(a) != SecureBool::Error
+                    && black_box(b) != SecureBool::Error
+                    && black_box(a) == black_box(b)
+            },
+            rng,
+        );
+
+        self.critical_bool(
+            || (black_box(a) == SecureBool::True && black_box(b) == SecureBool::True).into(),
+            rng,
+        )
+    }
+
+    /// Evaluates a quorum of independent checks and verifies that at least `threshold` of them
+    /// passed. Every check is evaluated, without short-circuiting, and the passing checks are
+    /// counted with redundant counters.
+    ///
+    /// Returns [`SecureBool::True`] if at least `threshold` checks returned [`SecureBool::True`],
+    /// and [`SecureBool::False`] otherwise. If the redundant counters disagree, the board securely
+    /// resets itself.
+    #[inline(always)]
+    pub fn critical_threshold(
+        &self,
+        checks: &[&dyn Fn() -> SecureBool],
+        threshold: usize,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        let mut passed: usize = black_box(0);
+        let mut passed_shadow: usize = black_box(0);
+        let mut failed: usize = black_box(0);
+
+        for check in checks {
+            let result = black_box(check());
+
+            // SAFETY: The counters are non-null, properly aligned, and initialized since they come
+            // from Rust variables.
+            unsafe {
+                if result == SecureBool::True {
+                    write_volatile(&mut passed, read_volatile(&passed) + 1);
+                } else {
+                    write_volatile(&mut failed, read_volatile(&failed) + 1);
+                }
+
+                write_volatile(
+                    &mut passed_shadow,
+                    read_volatile(&passed_shadow)
+                        + usize::from(black_box(result) == SecureBool::True),
+                );
+            }
+        }
+
+        // SAFETY: The counters are non-null, properly aligned, and initialized since they come
+        // from Rust variables.
+        self.critical_assert(
+            || unsafe {
+                read_volatile(&passed) == read_volatile(&passed_shadow)
+                    && read_volatile(&passed) + read_volatile(&failed) == black_box(checks.len())
+            },
+            rng,
+        );
+
+        // SAFETY: passed is non-null, properly aligned, and initialized since it comes from a Rust
+        // variable.
+        self.critical_bool(
+            || (unsafe { read_volatile(&passed) } >= black_box(threshold)).into(),
+            rng,
+        )
+    }
+
+    /// Verifies that a runtime length fits in a buffer of `N` elements, giving a hardened gate
+    /// before copying from a runtime-length source into a `[T; N]`.
+    ///
+    /// Returns [`SecureBool::True`] if `len <= N`, and [`SecureBool::False`] otherwise. If the
+    /// redundant checks disagree, the board securely resets itself.
+    ///
+    /// ```ignore
+    /// let mut buffer = [0u8; 64];
+    ///
+    /// if fip.critical_fits::<64>(src.len(), &mut rng) == SecureBool::True {
+    ///     buffer[..src.len()].copy_from_slice(src);
+    /// }
+    /// ```
+    #[inline(always)]
+    pub fn critical_fits<const N: usize>(
+        &self,
+        len: usize,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        self.critical_bool(|| (black_box(len) <= black_box(N)).into(), rng)
+    }
+
+    /// Stores `value` at `base[index]` after redundantly verifying that `index < bound`, for
+    /// writes through a computed pointer such as into an array of handlers. The bounds check is
+    /// repeated after the destination pointer is computed, and the pointer is recomputed and
+    /// compared, so a glitch on the index or the pointer arithmetic is detected. The store itself
+    /// is a [`FaultInjectionPrevention::critical_write()`], so `T` also needs the [`Default`] bound
+    /// of that function.
+    ///
+    /// If `index` is out of bounds or a fault injection is detected, the board securely resets
+    /// itself.
+    ///
+    /// # Safety
+    /// `base` must be valid for reads and writes of `bound` consecutive, properly aligned, and
+    /// initialized values of type `T`.
+    #[inline(always)]
+    pub unsafe fn critical_store_at<T>(
+        &self,
+        base: *mut T,
+        index: usize,
+        bound: usize,
+        value: T,
+        rng: &mut impl CryptoRngCore,
+    ) where
+        T: Eq + Copy + Default,
+    {
+        self.critical_assert(|| black_box(index) < black_box(bound), rng);
+
+        let dst = black_box(base.wrapping_add(index));
+
+        // SAFETY: index and dst are non-null, properly aligned, and initialized since they come
+        // from Rust variables.
+        self.critical_assert(
+            || unsafe {
+                let index = Arch::read_source(&index);
+
+                black_box(index) < black_box(bound)
+                    && Arch::read_source(&dst) == black_box(base).wrapping_add(black_box(index))
+            },
+            rng,
+        );
+
+        // SAFETY: index is in bounds, so the caller guarantees dst is valid for reads and writes,
+        // properly aligned, and initialized.
+        self.critical_write(
+            unsafe { &mut *dst },
+            value,
+            // SAFETY: dst comes from a Rust reference, so it's valid for writes and properly
+            // aligned.
+            |dst, src| unsafe { write_volatile(dst, src) },
+            rng,
+        );
+    }
+
+    /// Parses the Type-Length-Value entry at `offset` in `buf`, where each entry is a type byte, a
+    /// length byte, then `length` value bytes. The declared length is redundantly verified to fit
+    /// within `buf`, using overflow-safe arithmetic, before the value is sliced, so a glitch on the
+    /// length arithmetic can't cause an over-read.
+    ///
+    /// Returns the type, the value, and the offset of the next entry, or `None` if `offset` is at or
+    /// past the end of `buf`. If the header or declared length would over-read `buf`, or a fault
+    /// injection is detected, the board securely resets itself.
+    ///
+    /// ```ignore
+    /// let mut offset = 0;
+    ///
+    /// while let Some((tlv_type, value, next)) = fip.critical_tlv_next(blob, offset, &mut rng) {
+    ///     handle(tlv_type, value);
+    ///     offset = next;
+    /// }
+    /// ```
+    #[inline(always)]
+    pub fn critical_tlv_next<'a>(
+        &self,
+        buf: &'a [u8],
+        offset: usize,
+        rng: &mut impl CryptoRngCore,
+    ) -> Option<(u8, &'a [u8], usize)> {
+        if black_box(offset) >= buf.len() {
+            return None;
+        }
+
+        // The header must fit before the length byte can be read.
+        self.critical_assert(
+            || black_box(buf.len() - offset) >= black_box(TLV_HEADER_LEN),
+            rng,
+        );
+
+        let tlv_type = buf[offset];
+        let value_len = usize::from(black_box(buf[offset + 1]));
+        let value_start = offset + TLV_HEADER_LEN;
+
+        // Written as a subtraction from the remaining length so it can't overflow.
+        self.critical_assert(
+            || black_box(value_len) <= black_box(buf.len() - value_start),
+            rng,
+        );
+
+        let next_offset = black_box(value_start + value_len);
+
+        self.critical_assert(
+            || {
+                black_box(next_offset) <= buf.len()
+                    && black_box(next_offset) - value_start == value_len
+            },
+            rng,
+        );
+
+        Some((tlv_type, &buf[value_start..next_offset], next_offset))
+    }
+
+    /// Verifies that the clock frequency is within `tolerance_pct` percent of `expected_hz` before
+    /// timing-sensitive operations, since under- or over-clocking the device manipulates delays and
+    /// timing defenses. `measure` returns the measured frequency in Hz, e.g. by counting cycles
+    /// against a reference timer, and is called twice so each measurement is checked independently.
+    /// Tolerances above 100 percent are treated as 100 percent.
+    ///
+    /// Returns [`SecureBool::True`] once both measurements have been verified to be within
+    /// tolerance, so the result can gate the timing-sensitive operation. If either measurement is
+    /// out of range, which indicates clock tampering, the board securely resets itself.
+    #[inline(always)]
+    pub fn critical_verify_clock(
+        &self,
+        measure: impl Fn() -> u32,
+        expected_hz: u32,
+        tolerance_pct: u8,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        let tolerance_pct = u64::from(black_box(tolerance_pct).min(100));
+        let expected_hz = u64::from(black_box(expected_hz));
+        let min_hz = black_box(expected_hz * (100 - tolerance_pct) / 100);
+        let max_hz = black_box(expected_hz * (100 + tolerance_pct) / 100);
+
+        let measurement1 = u64::from(black_box(measure()));
+        self.critical_assert(|| (min_hz..=max_hz).contains(&black_box(measurement1)), rng);
+
+        let measurement2 = u64::from(black_box(measure()));
+        self.critical_assert(|| (min_hz..=max_hz).contains(&black_box(measurement2)), rng);
+
+        self.critical_bool(
+            || {
+                (black_box(measurement1) >= min_hz
+                    && black_box(measurement1) <= max_hz
+                    && black_box(measurement2) >= min_hz
+                    && black_box(measurement2) <= max_hz)
+                    .into()
+            },
+            rng,
+        )
+    }
+
+    /// Verifies a declared length field, e.g. from a certificate or public key being parsed, so a
+    /// glitched length can't pass the parser's bounds check.
+    ///
+    /// Returns [`SecureBool::True`] if `declared` is within `[min, max]` and no greater than the
+    /// `available` bytes, and [`SecureBool::False`] otherwise. If the redundant checks disagree,
+    /// the board securely resets itself.
+    #[inline(always)]
+    pub fn critical_check_field_len(
+        &self,
+        declared: usize,
+        min: usize,
+        max: usize,
+        available: usize,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        self.critical_bool(
+            || {
+                let declared = black_box(declared);
+
+                (declared >= black_box(min)
+                    && declared <= black_box(max)
+                    && declared <= black_box(available))
+                .into()
+            },
+            rng,
+        )
+    }
+
+    /// Advances a ratchet state, such as a forward-secure counter, that must never go backward.
+    /// `proposed` is redundantly verified to be strictly greater than `state` before it is written
+    /// with [`FaultInjectionPrevention::critical_write()`].
+    ///
+    /// Returns [`SecureBool::True`] if the state was advanced, and [`SecureBool::False`] without
+    /// advancing if `proposed` isn't strictly greater than `state`. If a fault injection is
+    /// detected, the board securely resets itself.
+    #[inline(always)]
+    pub fn critical_ratchet_advance(
+        &self,
+        state: &mut u32,
+        proposed: u32,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        let current = self.critical_read(state, rng).into_inner();
+        let advances =
+            self.critical_bool(|| (black_box(proposed) > black_box(current)).into(), rng);
+
+        if black_box(advances) == SecureBool::True {
+            // Re-check so a glitch on the branch above can't move the ratchet backward.
+            self.critical_assert(|| black_box(proposed) > black_box(current), rng);
+
+            self.critical_write(
+                state,
+                proposed,
+                // SAFETY: dst is non-null and properly aligned since it comes from a Rust
+                // reference.
+                |dst, src| unsafe { write_volatile(dst, src) },
+                rng,
+            );
+        }
+
+        advances
+    }
+
+    /// Runs every environment verification registered with
+    /// [`FaultInjectionPrevention::with_preconditions()`], e.g. VTOR, MPU, privilege, debug lock,
+    /// and clock checks, as a single "are we in a trustworthy state" gate before granting a
+    /// sensitive capability. Every verification runs, without short-circuiting.
+    ///
+    /// Returns [`SecureBool::True`] if all verifications passed, and [`SecureBool::False`] if any
+    /// failed or none are registered, so a missing configuration fails closed. If a fault
+    /// injection is detected, the board securely resets itself.
+    #[inline(always)]
+    pub fn critical_preconditions(&self, mut rng: &mut impl CryptoRngCore) -> SecureBool {
+        // SAFETY: preconditions is non-null, properly aligned, and initialized since it comes
+        // from a Rust reference.
+        let preconditions = unsafe { read_volatile(&self.preconditions) };
+        let mut passed: usize = black_box(0);
+        let mut passed_shadow: usize = black_box(0);
+
+        for precondition in preconditions {
+            let result = black_box(precondition(self, &mut rng));
+
+            // SAFETY: The counters are non-null, properly aligned, and initialized since they come
+            // from Rust variables.
+            unsafe {
+                if result == SecureBool::True {
+                    write_volatile(&mut passed, read_volatile(&passed) + 1);
+                }
+
+                write_volatile(
+                    &mut passed_shadow,
+                    read_volatile(&passed_shadow)
+                        + usize::from(black_box(result) == SecureBool::True),
+                );
+            }
+        }
+
+        // SAFETY: The counters are non-null, properly aligned, and initialized since they come
+        // from Rust variables.
+        self.critical_assert(
+            || unsafe { read_volatile(&passed) == read_volatile(&passed_shadow) },
+            rng,
+        );
+
+        // SAFETY: Same as above.
+        self.critical_bool(
+            || unsafe {
+                (!preconditions.is_empty()
+                    && read_volatile(&passed) == black_box(preconditions.len()))
+                .into()
+            },
+            rng,
+        )
+    }
+
+    /// Checks whether `command` is permitted in the current lifecycle `state`, for gating protocol
+    /// commands by state. The pair is allowed if it appears in `table`. Every entry of the table is
+    /// compared, without short-circuiting, on each redundant pass.
+    ///
+    /// Returns [`SecureBool::True`] if `(state, command)` is in `table`, and [`SecureBool::False`]
+    /// otherwise. If the redundant passes disagree, the board securely resets itself.
+    #[inline(always)]
+    pub fn critical_command_allowed<S: Copy + Eq, C: Copy + Eq>(
+        &self,
+        state: S,
+        command: C,
+        table: &[(S, C)],
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        self.critical_bool(
+            || {
+                let matches = black_box(table).iter().fold(0usize, |matches, &(s, c)| {
+                    matches + usize::from(black_box(s == state) & black_box(c == command))
+                });
+
+                (black_box(matches) != 0).into()
+            },
+            rng,
+        )
+    }
+
+    /// Confirms physical presence before a destructive security action, such as a factory reset or
+    /// key erase, by sampling a button `required_samples` times with a random delay of 50,000 to
+    /// 100,000 cycles between samples. Every sample is taken and counted in two volatile counters,
+    /// so a bouncing button or a single glitched reading isn't enough.
+    ///
+    /// Returns [`SecureBool::True`] if every sample read as pressed, and [`SecureBool::False`]
+    /// otherwise, including when `required_samples` is 0. If `read_button` returns
+    /// [`SecureBool::Error`] or the counters disagree, the board securely resets itself.
+    #[inline(always)]
+    pub fn critical_confirm_presence(
+        &self,
+        read_button: impl Fn() -> SecureBool,
+        required_samples: u32,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        let mut pressed: u32 = black_box(0);
+        let mut pressed_shadow: u32 = black_box(0);
+
+        for sample in 0..black_box(required_samples) {
+            if sample != 0 {
+                // The range is always valid since the bounds are constants.
+                self.secure_random_delay_cycles(
+                    rng,
+                    PRESENCE_SAMPLE_MIN_CYCLES,
+                    PRESENCE_SAMPLE_MAX_CYCLES,
+                )
+                .unwrap();
+            }
+
+            let reading = black_box(read_button());
+            self.critical_assert(|| black_box(reading) != SecureBool::Error, rng);
+
+            // SAFETY: The counters are non-null, properly aligned, and initialized since they come
+            // from Rust variables.
+            unsafe {
+                write_volatile(
+                    &mut pressed,
+                    read_volatile(&pressed) + u32::from(reading == SecureBool::True),
+                );
+                write_volatile(
+                    &mut pressed_shadow,
+                    read_volatile(&pressed_shadow)
+                        + u32::from(black_box(reading) == SecureBool::True),
+                );
+            }
+        }
+
+        // SAFETY: The counters are non-null, properly aligned, and initialized since they come
+        // from Rust variables.
+        self.critical_assert(
+            || unsafe { read_volatile(&pressed) == read_volatile(&pressed_shadow) },
+            rng,
+        );
+
+        // SAFETY: Same as above.
+        self.critical_bool(
+            || unsafe {
+                (black_box(required_samples) != 0
+                    && read_volatile(&pressed) == black_box(required_samples))
+                .into()
+            },
+            rng,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::mock;
+    use crate::test_util::{resets, TestRng};
+    use crate::Precondition;
+    use core::cell::Cell;
+
+    #[test]
+    fn critical_cross_check_time_within_tolerance() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        for (t1, t2, agrees) in [
+            (1000, 1000, SecureBool::True),
+            (1000, 1004, SecureBool::True),
+            (1004, 1000, SecureBool::True),
+            // Exactly at the tolerance
+            (1000, 1005, SecureBool::True),
+            (1005, 1000, SecureBool::True),
+            // Just over the tolerance
+            (1000, 1006, SecureBool::False),
+            (1006, 1000, SecureBool::False),
+            (0, u64::MAX, SecureBool::False),
+        ] {
+            assert!(fip.critical_cross_check_time(t1, t2, 5, &mut rng) == agrees);
+        }
+    }
+
+    #[test]
+    fn critical_assert_time_agrees_resets_on_divergence() {
+        let fip = FaultInjectionPrevention::new();
+
+        fip.critical_assert_time_agrees(1000, 1005, 5, &mut TestRng(1));
+
+        assert!(resets(|| fip.critical_assert_time_agrees(
+            1000,
+            1006,
+            5,
+            &mut TestRng(1)
+        )));
+    }
+
+    #[test]
+    fn critical_sensor_agree_combines_agreeing_sensors() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        assert!(
+            fip.critical_sensor_agree(SecureBool::True, SecureBool::True, &mut rng)
+                == SecureBool::True
+        );
+        assert!(
+            fip.critical_sensor_agree(SecureBool::False, SecureBool::False, &mut rng)
+                == SecureBool::False
+        );
+    }
+
+    #[test]
+    fn critical_sensor_agree_resets_on_disagreement_or_error() {
+        let fip = FaultInjectionPrevention::new();
+        let (t, f, e) = (SecureBool::True, SecureBool::False, SecureBool::Error);
+
+        for (a, b) in [(t, f), (f, t), (e, e), (t, e), (e, t), (f, e), (e, f)] {
+            assert!(resets(|| {
+                fip.critical_sensor_agree(a, b, &mut TestRng(1));
+            }));
+        }
+    }
+
+    #[test]
+    fn critical_threshold_compares_passed_checks_to_threshold() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+        let pass = || SecureBool::True;
+        let fail = || SecureBool::False;
+        let error = || SecureBool::Error;
+        // 2 of 4 checks pass, and an error doesn't count as a pass.
+        let checks: [&dyn Fn() -> SecureBool; 4] = [&pass, &fail, &pass, &error];
+
+        for (threshold, met) in [
+            (0, SecureBool::True),
+            (1, SecureBool::True),
+            (2, SecureBool::True),
+            (3, SecureBool::False),
+            (5, SecureBool::False),
+        ] {
+            assert!(fip.critical_threshold(&checks, threshold, &mut rng) == met);
+        }
+
+        assert!(fip.critical_threshold(&[], 0, &mut rng) == SecureBool::True);
+        assert!(fip.critical_threshold(&[], 1, &mut rng) == SecureBool::False);
+    }
+
+    #[test]
+    fn critical_threshold_evaluates_every_check() {
+        let fip = FaultInjectionPrevention::new();
+        let calls = Cell::new(0);
+        let pass = || {
+            calls.set(calls.get() + 1);
+            SecureBool::True
+        };
+        let checks: [&dyn Fn() -> SecureBool; 3] = [&pass, &pass, &pass];
+
+        assert!(fip.critical_threshold(&checks, 1, &mut TestRng(1)) == SecureBool::True);
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn critical_fits_compares_length_to_capacity() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        for (len, fits) in [
+            (0, SecureBool::True),
+            (63, SecureBool::True),
+            (64, SecureBool::True),
+            (65, SecureBool::False),
+            (usize::MAX, SecureBool::False),
+        ] {
+            assert!(fip.critical_fits::<64>(len, &mut rng) == fits);
+        }
+
+        assert!(fip.critical_fits::<0>(0, &mut rng) == SecureBool::True);
+        assert!(fip.critical_fits::<0>(1, &mut rng) == SecureBool::False);
+    }
+
+    #[test]
+    fn critical_store_at_stores_at_valid_indices() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+        let mut handlers = [0u32; 4];
+
+        for index in 0..handlers.len() {
+            // SAFETY: handlers is valid for 4 u32s.
+            unsafe {
+                fip.critical_store_at(handlers.as_mut_ptr(), index, 4, index as u32 + 1, &mut rng)
+            };
+        }
+
+        assert_eq!(handlers, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn critical_store_at_resets_on_out_of_bounds_index() {
+        let fip = FaultInjectionPrevention::new();
+
+        for index in [4, 5, usize::MAX] {
+            let mut handlers = [0u32; 4];
+
+            assert!(resets(|| {
+                // SAFETY: handlers is valid for 4 u32s.
+                unsafe {
+                    fip.critical_store_at(handlers.as_mut_ptr(), index, 4, 1, &mut TestRng(1))
+                };
+            }));
+            assert_eq!(handlers, [0; 4]);
+        }
+    }
+
+    #[test]
+    fn critical_store_at_resets_when_store_misses_destination() {
+        let fip = FaultInjectionPrevention::new();
+        let mut handlers = [0u32; 4];
+
+        // The read-back sees another value at the destination, as if the store landed elsewhere.
+        // It follows the 4 evaluations of the re-check, which read the index and the destination.
+        mock::change_source_after(8);
+        assert!(resets(|| {
+            // SAFETY: handlers is valid for 4 u32s.
+            unsafe { fip.critical_store_at(handlers.as_mut_ptr(), 2, 4, 1, &mut TestRng(1)) };
+        }));
+        assert_eq!(handlers, [0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn critical_store_at_resets_when_index_changes_after_bounds_check() {
+        let fip = FaultInjectionPrevention::new();
+        let mut handlers = [0u32; 4];
+
+        mock::change_source_after(0);
+        assert!(resets(|| {
+            // SAFETY: handlers is valid for 4 u32s.
+            unsafe { fip.critical_store_at(handlers.as_mut_ptr(), 2, 4, 1, &mut TestRng(1)) };
+        }));
+        assert_eq!(handlers, [0; 4]);
+    }
+
+    #[test]
+    fn critical_store_at_resets_when_destination_changes_after_bounds_check() {
+        let fip = FaultInjectionPrevention::new();
+        let mut handlers = [0u32; 4];
+
+        // The index still passes the bounds check, but the computed destination differs from the
+        // recomputed one.
+        mock::change_source_after(1);
+        assert!(resets(|| {
+            // SAFETY: handlers is valid for 4 u32s.
+            unsafe { fip.critical_store_at(handlers.as_mut_ptr(), 2, 4, 1, &mut TestRng(1)) };
+        }));
+        assert_eq!(handlers, [0; 4]);
+    }
+
+    #[test]
+    fn critical_tlv_next_parses_well_formed_entries() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+        let blob = [0x01, 0x02, 0xAA, 0xBB, 0x02, 0x00, 0x03, 0x01, 0xCC];
+        let mut entries = std::vec::Vec::new();
+        let mut offset = 0;
+
+        while let Some((tlv_type, value, next)) = fip.critical_tlv_next(&blob, offset, &mut rng) {
+            entries.push((tlv_type, value));
+            offset = next;
+        }
+
+        assert_eq!(
+            entries,
+            [
+                (0x01, &[0xAA, 0xBB][..]),
+                (0x02, &[][..]),
+                (0x03, &[0xCC][..])
+            ]
+        );
+        assert_eq!(offset, blob.len());
+        assert_eq!(fip.critical_tlv_next(&blob, usize::MAX, &mut rng), None);
+    }
+
+    #[test]
+    fn critical_tlv_next_resets_on_over_read() {
+        let fip = FaultInjectionPrevention::new();
+
+        // A declared length past the end of the buffer, the maximum declared length, and a
+        // truncated header
+        for (blob, offset) in [
+            (&[0x01, 0x03, 0xAA, 0xBB][..], 0),
+            (&[0x01, 0xFF, 0xAA][..], 0),
+            (&[0x01, 0x00, 0x02][..], 2),
+        ] {
+            assert!(resets(|| {
+                fip.critical_tlv_next(blob, offset, &mut TestRng(1));
+            }));
+        }
+    }
+
+    #[test]
+    fn critical_verify_clock_accepts_clock_within_tolerance() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        for measured in [64_000_000, 60_800_000, 67_200_000] {
+            let verified = fip.critical_verify_clock(|| measured, 64_000_000, 5, &mut rng);
+            assert!(verified == SecureBool::True);
+        }
+
+        // Tolerances above 100 percent are clamped, so a stopped clock is still within range.
+        assert!(fip.critical_verify_clock(|| 0, 64_000_000, 200, &mut rng) == SecureBool::True);
+    }
+
+    #[test]
+    fn critical_verify_clock_resets_on_slow_or_fast_clock() {
+        let fip = FaultInjectionPrevention::new();
+
+        for measured in [60_799_999, 0, 67_200_001, u32::MAX] {
+            assert!(resets(|| {
+                fip.critical_verify_clock(|| measured, 64_000_000, 5, &mut TestRng(1));
+            }));
+        }
+    }
+
+    #[test]
+    fn critical_verify_clock_resets_when_second_measurement_is_out_of_range() {
+        let fip = FaultInjectionPrevention::new();
+        let measurements = Cell::new(0);
+        let measure = || {
+            measurements.set(measurements.get() + 1);
+            if measurements.get() == 1 {
+                64_000_000
+            } else {
+                32_000_000
+            }
+        };
+
+        assert!(resets(|| {
+            fip.critical_verify_clock(measure, 64_000_000, 5, &mut TestRng(1));
+        }));
+        assert_eq!(measurements.get(), 2);
+    }
+
+    #[test]
+    fn critical_check_field_len_checks_each_bound() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        // Declared length, with a [32, 512] range and 256 available bytes
+        for (declared, valid) in [
+            (31, SecureBool::False),
+            (32, SecureBool::True),
+            (256, SecureBool::True),
+            (257, SecureBool::False),
+            (512, SecureBool::False),
+            (usize::MAX, SecureBool::False),
+        ] {
+            assert!(fip.critical_check_field_len(declared, 32, 512, 256, &mut rng) == valid);
+        }
+
+        // With more bytes available than the maximum
+        assert!(fip.critical_check_field_len(512, 32, 512, 1024, &mut rng) == SecureBool::True);
+        assert!(fip.critical_check_field_len(513, 32, 512, 1024, &mut rng) == SecureBool::False);
+    }
+
+    #[test]
+    fn critical_ratchet_advance_advances_only_forward() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+        let mut state = 10;
+
+        for (proposed, advances, after) in [
+            (11, SecureBool::True, 11),
+            (11, SecureBool::False, 11),
+            (3, SecureBool::False, 11),
+            (u32::MAX, SecureBool::True, u32::MAX),
+            (0, SecureBool::False, u32::MAX),
+        ] {
+            assert!(fip.critical_ratchet_advance(&mut state, proposed, &mut rng) == advances);
+            assert_eq!(state, after);
+        }
+    }
+
+    #[test]
+    fn critical_ratchet_advance_resets_on_glitched_state() {
+        let fip = FaultInjectionPrevention::new();
+
+        // The redundant reads of the state disagree.
+        mock::change_source_after(3);
+        assert!(resets(|| {
+            fip.critical_ratchet_advance(&mut 10, 11, &mut TestRng(1));
+        }));
+
+        // The state doesn't read back as advanced after the write.
+        mock::change_source_after(4);
+        assert!(resets(|| {
+            fip.critical_ratchet_advance(&mut 10, 11, &mut TestRng(1));
+        }));
+    }
+
+    std::thread_local! {
+        // Number of preconditions run on this thread
+        static PRECONDITION_RUNS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn privileged(fip: &FaultInjectionPrevention, mut rng: &mut dyn CryptoRngCore) -> SecureBool {
+        PRECONDITION_RUNS.set(PRECONDITION_RUNS.get() + 1);
+        fip.critical_verify_privilege(SecureBool::True, &mut rng)
+    }
+
+    fn clock_ok(fip: &FaultInjectionPrevention, mut rng: &mut dyn CryptoRngCore) -> SecureBool {
+        PRECONDITION_RUNS.set(PRECONDITION_RUNS.get() + 1);
+        fip.critical_verify_clock(|| 64_000_000, 64_000_000, 5, &mut rng)
+    }
+
+    fn debug_unlocked(_: &FaultInjectionPrevention, _: &mut dyn CryptoRngCore) -> SecureBool {
+        PRECONDITION_RUNS.set(PRECONDITION_RUNS.get() + 1);
+        SecureBool::False
+    }
+
+    #[test]
+    fn critical_preconditions_passes_when_all_pass() {
+        static PRECONDITIONS: [Precondition; 2] = [privileged, clock_ok];
+        let fip = FaultInjectionPrevention::new().with_preconditions(&PRECONDITIONS);
+
+        mock::set_control(0);
+        assert!(fip.critical_preconditions(&mut TestRng(1)) == SecureBool::True);
+        assert_eq!(PRECONDITION_RUNS.get(), 2);
+
+        // Running unprivileged fails the privilege check.
+        mock::set_control(1);
+        assert!(fip.critical_preconditions(&mut TestRng(1)) == SecureBool::False);
+    }
+
+    #[test]
+    fn critical_preconditions_fails_when_one_fails() {
+        static PRECONDITIONS: [Precondition; 3] = [debug_unlocked, privileged, clock_ok];
+        let fip = FaultInjectionPrevention::new().with_preconditions(&PRECONDITIONS);
+
+        mock::set_control(0);
+        assert!(fip.critical_preconditions(&mut TestRng(1)) == SecureBool::False);
+        // Every check runs, without short-circuiting.
+        assert_eq!(PRECONDITION_RUNS.get(), 3);
+    }
+
+    #[test]
+    fn critical_preconditions_fails_without_preconditions() {
+        let fip = FaultInjectionPrevention::new();
+
+        assert!(fip.critical_preconditions(&mut TestRng(1)) == SecureBool::False);
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum Lifecycle {
+        Provisioning,
+        Locked,
+        Unlocked,
+    }
+
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum Command {
+        WriteKey,
+        Unlock,
+        ReadMemory,
+    }
+
+    const ALLOWED_COMMANDS: [(Lifecycle, Command); 4] = [
+        (Lifecycle::Provisioning, Command::WriteKey),
+        (Lifecycle::Locked, Command::Unlock),
+        (Lifecycle::Unlocked, Command::ReadMemory),
+        (Lifecycle::Unlocked, Command::Unlock),
+    ];
+
+    /// Checks whether `command` is allowed in `state` by `ALLOWED_COMMANDS`.
+    fn command_allowed(state: Lifecycle, command: Command) -> SecureBool {
+        FaultInjectionPrevention::new().critical_command_allowed(
+            state,
+            command,
+            &ALLOWED_COMMANDS,
+            &mut TestRng(1),
+        )
+    }
+
+    #[test]
+    fn critical_command_allowed_accepts_allowed_pairs() {
+        for (state, command) in ALLOWED_COMMANDS {
+            assert!(command_allowed(state, command) == SecureBool::True);
+        }
+    }
+
+    #[test]
+    fn critical_command_allowed_rejects_disallowed_pairs() {
+        assert!(command_allowed(Lifecycle::Locked, Command::ReadMemory) == SecureBool::False);
+        assert!(command_allowed(Lifecycle::Provisioning, Command::Unlock) == SecureBool::False);
+
+        let fip = FaultInjectionPrevention::new();
+        let empty: [(Lifecycle, Command); 0] = [];
+        assert!(
+            fip.critical_command_allowed(
+                Lifecycle::Locked,
+                Command::Unlock,
+                &empty,
+                &mut TestRng(1)
+            ) == SecureBool::False
+        );
+    }
+
+    #[test]
+    fn critical_command_allowed_rejects_adjacent_but_wrong_pairs() {
+        // Both the state and the command are in the table, next to each other, but not as a pair.
+        assert!(command_allowed(Lifecycle::Locked, Command::WriteKey) == SecureBool::False);
+        assert!(command_allowed(Lifecycle::Provisioning, Command::ReadMemory) == SecureBool::False);
+        assert!(command_allowed(Lifecycle::Unlocked, Command::WriteKey) == SecureBool::False);
+    }
+
+    /// A command that only compares equal the first time, like a glitched comparison.
+    #[derive(Clone, Copy)]
+    struct GlitchedCommand<'a> {
+        comparisons: &'a Cell<usize>,
+    }
+
+    impl PartialEq for GlitchedCommand<'_> {
+        fn eq(&self, _: &Self) -> bool {
+            self.comparisons.set(self.comparisons.get() + 1);
+            self.comparisons.get() <= 1
+        }
+    }
+
+    impl Eq for GlitchedCommand<'_> {}
+
+    #[test]
+    fn critical_command_allowed_resets_when_passes_disagree() {
+        let fip = FaultInjectionPrevention::new();
+        let comparisons = Cell::new(0);
+        let command = GlitchedCommand {
+            comparisons: &comparisons,
+        };
+
+        assert!(resets(|| {
+            fip.critical_command_allowed(
+                Lifecycle::Locked,
+                command,
+                &[(Lifecycle::Locked, command)],
+                &mut TestRng(1),
+            );
+        }));
+    }
+
+    /// Confirms presence with a mock button that returns `readings` in order, one per sample, and
+    /// returns the result and the number of samples taken.
+    fn confirm_presence(readings: &[SecureBool]) -> (SecureBool, usize) {
+        let fip = FaultInjectionPrevention::new();
+        let samples = Cell::new(0);
+
+        let result = fip.critical_confirm_presence(
+            || {
+                samples.set(samples.get() + 1);
+                readings[samples.get() - 1]
+            },
+            readings.len() as u32,
+            &mut TestRng(1),
+        );
+
+        (result, samples.get())
+    }
+
+    #[test]
+    fn critical_confirm_presence_confirms_steady_press() {
+        assert!(confirm_presence(&[SecureBool::True; 5]) == (SecureBool::True, 5));
+    }
+
+    #[test]
+    fn critical_confirm_presence_rejects_bouncing_button() {
+        use SecureBool::{False, True};
+
+        assert!(confirm_presence(&[True, False, True, False, True]) == (SecureBool::False, 5));
+        assert!(confirm_presence(&[True, True, True, True, False]) == (SecureBool::False, 5));
+    }
+
+    #[test]
+    fn critical_confirm_presence_rejects_single_pressed_spike() {
+        use SecureBool::{False, True};
+
+        assert!(confirm_presence(&[False, False, True, False, False]) == (SecureBool::False, 5));
+        assert!(confirm_presence(&[False; 5]) == (SecureBool::False, 5));
+        assert!(confirm_presence(&[]) == (SecureBool::False, 0));
+    }
+
+    #[test]
+    fn critical_confirm_presence_resets_on_error_reading() {
+        use SecureBool::{Error, True};
+
+        assert!(resets(|| {
+            confirm_presence(&[True, Error, True]);
+        }));
+    }
+}
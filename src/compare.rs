@@ -0,0 +1,841 @@
+//! Fault-injection resistant, constant-time comparisons of byte slices.
+
+use crate::platform::{Arch, Platform};
+use crate::{helper, secure_enum, FaultInjectionPrevention, SecureBool, SecureEnum};
+use core::cmp::Ordering;
+use core::hint::black_box;
+use core::ptr::{read_volatile, write_volatile};
+use rand_core::CryptoRngCore;
+use sha2::{Digest, Sha256};
+
+// Minimum number of 0xFF padding bytes in a PKCS#1 v1.5 signature block
+const PKCS1_MIN_PADDING_LEN: usize = 8;
+
+// Block size used when feeding a message to a MAC
+const MAC_BLOCK_LEN: usize = 16;
+
+secure_enum! {
+    /// The result of a comparison returned by
+    /// [`FaultInjectionPrevention::critical_compare_by()`], like [`Ordering`] but with large,
+    /// mutually distant discriminants.
+    pub enum SecureOrdering {
+        Less,
+        Equal,
+        Greater,
+    }
+}
+
+impl From<Ordering> for SecureOrdering {
+    fn from(ordering: Ordering) -> SecureOrdering {
+        match ordering {
+            Ordering::Less => SecureOrdering::Less,
+            Ordering::Equal => SecureOrdering::Equal,
+            Ordering::Greater => SecureOrdering::Greater,
+        }
+    }
+}
+
+/// Checks the `00 01 FF..FF 00` structure of a PKCS#1 v1.5 signature block in constant time with
+/// respect to the contents of `em`.
+#[inline(always)]
+fn pkcs1_padding_valid(em: &[u8]) -> bool {
+    if em.len() < PKCS1_MIN_PADDING_LEN + 3 {
+        return false;
+    }
+
+    let mut invalid = em[0] | (em[1] ^ 0x01);
+    let mut in_padding: u8 = 1;
+    let mut padding_len: usize = 0;
+
+    for &byte in &em[2..] {
+        let is_separator = helper::ct_eq_u8(black_box(byte), 0x00);
+        let is_padding = helper::ct_eq_u8(black_box(byte), 0xFF);
+
+        // Until the separator, every byte must be 0xFF.
+        invalid |= in_padding & !(is_padding | is_separator) & 1;
+        padding_len += usize::from(in_padding & is_padding);
+        in_padding &= !is_separator & 1;
+    }
+
+    // The separator must be present, after enough padding.
+    invalid |= in_padding;
+    invalid |= u8::from(padding_len < PKCS1_MIN_PADDING_LEN);
+
+    black_box(invalid) == 0
+}
+
+/// Computes the response bound to `challenge`, i.e. `SHA-256(len(challenge) || challenge ||
+/// expected)` with the length as a little-endian `u64`. The length prefix keeps the boundary
+/// between the challenge and the expected value unambiguous.
+#[inline(always)]
+fn bind_to_challenge(challenge: &[u8], expected: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+
+    hasher.update((challenge.len() as u64).to_le_bytes());
+    hasher.update(challenge);
+    hasher.update(expected);
+
+    hasher.finalize().into()
+}
+
+impl FaultInjectionPrevention {
+    /// Verifies the `00 01 FF..FF 00` padding structure of a PKCS#1 v1.5 signature block before
+    /// signature verification, since glitching the padding checks is a known signature bypass. At
+    /// least 8 bytes of `0xFF` padding are required. The check runs in constant time with respect
+    /// to the contents of `em` and is repeated for each redundant pass.
+    ///
+    /// Returns [`SecureBool::True`] if the padding is well-formed, and [`SecureBool::False`]
+    /// otherwise. If the redundant passes disagree, the board securely resets itself.
+    #[inline(always)]
+    pub fn critical_check_padding(&self, em: &[u8], rng: &mut impl CryptoRngCore) -> SecureBool {
+        self.critical_bool(|| pkcs1_padding_valid(black_box(em)).into(), rng)
+    }
+
+    /// Compares `needle` against the prefix of `haystack` in constant time, for received fields
+    /// that are compared against an expected value where trailing padding is allowed. The
+    /// comparison is repeated for each redundant pass.
+    ///
+    /// Returns [`SecureBool::True`] if `haystack` starts with `needle`, and [`SecureBool::False`]
+    /// otherwise, including when `haystack` is shorter than `needle`. If the redundant passes
+    /// disagree, the board securely resets itself.
+    #[inline(always)]
+    pub fn critical_memcmp_prefix(
+        &self,
+        haystack: &[u8],
+        needle: &[u8],
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        self.critical_bool(
+            || {
+                let haystack = black_box(haystack);
+                let needle = black_box(needle);
+
+                (haystack.len() >= needle.len()
+                    && helper::ct_eq_slices(&haystack[..needle.len()], needle))
+                .into()
+            },
+            rng,
+        )
+    }
+
+    /// Compares `candidate` against a fixed-length secret, such as a PIN or an unlock key, in
+    /// constant time. Since the length of the secret is fixed, a candidate of a different length is
+    /// treated as an attack rather than a mismatch. The comparison is repeated for each redundant
+    /// pass, and a random delay runs before returning.
+    ///
+    /// Returns [`SecureBool::True`] if `candidate` equals `secret`, and [`SecureBool::False`]
+    /// otherwise. If the lengths differ or the redundant passes disagree, the board securely
+    /// resets itself.
+    #[inline(always)]
+    pub fn critical_ct_eq_secret(
+        &self,
+        secret: &[u8],
+        candidate: &[u8],
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        self.critical_assert(
+            || black_box(secret.len()) == black_box(candidate.len()),
+            rng,
+        );
+
+        let matches = self.critical_bool(
+            || helper::ct_eq_slices(black_box(secret), black_box(candidate)).into(),
+            rng,
+        );
+
+        self.secure_random_delay(rng);
+        matches
+    }
+
+    /// Verifies a response to a challenge, binding the comparison to the specific `challenge` so a
+    /// response captured for one challenge can't be replayed for another. The expected response is
+    /// `SHA-256(len(challenge) || challenge || expected)`, with the challenge length as a
+    /// little-endian `u64`, which is computed twice and compared against `response` in constant
+    /// time for each redundant pass, after a random delay.
+    ///
+    /// Returns [`SecureBool::True`] if `response` is the expected response for `challenge`, and
+    /// [`SecureBool::False`] otherwise. If the redundant computations or passes disagree, the board
+    /// securely resets itself.
+    #[inline(always)]
+    pub fn critical_verify_challenge(
+        &self,
+        challenge: &[u8],
+        response: &[u8],
+        expected: &[u8],
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        self.critical_verify_challenge_with(challenge, response, expected, bind_to_challenge, rng)
+    }
+
+    /// Same as [`FaultInjectionPrevention::critical_verify_challenge()`], but the expected response
+    /// is `bind(challenge, expected)`, for protocols that bind the challenge with another hash.
+    ///
+    /// The replay protection depends entirely on `bind`, which must be a collision-resistant hash
+    /// over both arguments that keeps the boundary between them unambiguous. Anything weaker, like
+    /// XOR or a plain concatenation, lets a response be replayed for another challenge, which
+    /// can't be detected here.
+    ///
+    /// ```ignore
+    /// let bind = |challenge: &[u8], expected: &[u8]| {
+    ///     let mut hasher = Sha512_256::new();
+    ///
+    ///     hasher.update((challenge.len() as u64).to_le_bytes());
+    ///     hasher.update(challenge);
+    ///     hasher.update(expected);
+    ///     hasher.finalize().into()
+    /// };
+    ///
+    /// fip.critical_verify_challenge_with(&challenge, &response, &expected, bind, &mut rng);
+    /// ```
+    #[inline(always)]
+    pub fn critical_verify_challenge_with(
+        &self,
+        challenge: &[u8],
+        response: &[u8],
+        expected: &[u8],
+        bind: impl Fn(&[u8], &[u8]) -> [u8; 32],
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        let bound = black_box(bind(black_box(challenge), black_box(expected)));
+        let bound_shadow = black_box(bind(black_box(challenge), black_box(expected)));
+
+        self.critical_assert(|| helper::ct_eq_slices(&bound, &bound_shadow), rng);
+
+        self.secure_random_delay(rng);
+
+        self.critical_bool(
+            || helper::ct_eq_slices(black_box(response), black_box(&bound)).into(),
+            rng,
+        )
+    }
+
+    /// Verifies a link of a hash chain, such as a tamper-evident log where each entry includes the
+    /// hash of the previous one. `hash_fn(prev_hash, entry)` is computed twice, and both results
+    /// are compared against `claimed_hash` in constant time for each redundant pass.
+    ///
+    /// Returns [`SecureBool::True`] if `claimed_hash` is the hash of `prev_hash` and `entry`, and
+    /// [`SecureBool::False`] otherwise. If the redundant computations or passes disagree, the board
+    /// securely resets itself.
+    #[inline(always)]
+    pub fn critical_verify_chain_link(
+        &self,
+        prev_hash: &[u8; 32],
+        entry: &[u8],
+        claimed_hash: &[u8; 32],
+        hash_fn: impl Fn(&[u8], &[u8]) -> [u8; 32],
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        let hash = black_box(hash_fn(black_box(prev_hash), black_box(entry)));
+        let hash_shadow = black_box(hash_fn(black_box(prev_hash), black_box(entry)));
+
+        self.critical_assert(|| helper::ct_eq_slices(&hash, &hash_shadow), rng);
+
+        self.critical_bool(
+            || {
+                let matches = helper::ct_eq_slices(black_box(&hash), black_box(claimed_hash));
+                let matches_shadow =
+                    helper::ct_eq_slices(black_box(&hash_shadow), black_box(claimed_hash));
+
+                (black_box(matches) & black_box(matches_shadow)).into()
+            },
+            rng,
+        )
+    }
+
+    /// Computes a MAC over the full message, protecting against glitches that skip the last blocks
+    /// of the computation. `msg` is fed to `mac_update` in 16-byte blocks while the number of bytes
+    /// fed is tracked in two redundant counters, and after `mac_finalize`, both are verified to
+    /// equal `msg.len()`.
+    ///
+    /// Returns the MAC. If fewer than `msg.len()` bytes were fed, or the counters disagree, the
+    /// board securely resets itself.
+    #[inline(always)]
+    pub fn critical_mac_full(
+        &self,
+        msg: &[u8],
+        mut mac_update: impl FnMut(&[u8]),
+        mac_finalize: impl FnOnce() -> [u8; 16],
+        rng: &mut impl CryptoRngCore,
+    ) -> [u8; 16] {
+        self.verify_self();
+
+        let msg = black_box(msg);
+        let mut offset: usize = black_box(0);
+        let mut fed: usize = black_box(0);
+        let mut fed_shadow: usize = black_box(0);
+
+        // SAFETY: The counters are non-null, properly aligned, and initialized since they come
+        // from Rust variables.
+        while unsafe { Arch::read_source(&offset) } < msg.len() {
+            let block = &msg[offset..msg.len().min(offset + MAC_BLOCK_LEN)];
+            mac_update(black_box(block));
+
+            // SAFETY: Same as above.
+            unsafe {
+                write_volatile(&mut fed, read_volatile(&fed) + block.len());
+                write_volatile(
+                    &mut fed_shadow,
+                    read_volatile(&fed_shadow) + black_box(block).len(),
+                );
+            }
+
+            offset += block.len();
+        }
+
+        helper::dsb();
+
+        let mac = black_box(mac_finalize());
+
+        // SAFETY: The counters are non-null, properly aligned, and initialized since they come
+        // from Rust variables.
+        self.critical_assert(
+            || unsafe {
+                read_volatile(&fed) == black_box(msg.len())
+                    && read_volatile(&fed_shadow) == black_box(msg.len())
+            },
+            rng,
+        );
+
+        mac
+    }
+
+    /// Runs an operation whose result is a side effect on a buffer, such as encrypting into it,
+    /// twice, once into `scratch_a` and once into `scratch_b`, and verifies in constant time that
+    /// both runs produced the same bytes. Both buffers are zeroed before the runs, so the
+    /// operation starts from the same state each time. On return, `scratch_a` holds the verified
+    /// result.
+    ///
+    /// If the buffers have different lengths or the runs diverge, the board securely resets
+    /// itself.
+    #[inline(always)]
+    pub fn critical_redundant_effect(
+        &self,
+        run: impl Fn(&mut [u8]),
+        scratch_a: &mut [u8],
+        scratch_b: &mut [u8],
+        rng: &mut impl CryptoRngCore,
+    ) {
+        self.verify_self();
+
+        self.critical_assert(
+            || black_box(scratch_a.len()) == black_box(scratch_b.len()),
+            rng,
+        );
+
+        for byte in scratch_a.iter_mut().chain(scratch_b.iter_mut()) {
+            // SAFETY: byte is non-null and properly aligned since it comes from a Rust reference.
+            unsafe {
+                write_volatile(byte, 0);
+            }
+        }
+
+        helper::dsb();
+        run(black_box(&mut *scratch_a));
+        helper::dsb();
+
+        // Delay so a single glitch can't hit both runs at the same point.
+        self.secure_random_delay(rng);
+
+        run(black_box(&mut *scratch_b));
+        helper::dsb();
+
+        self.critical_assert(
+            || helper::ct_eq_slices(black_box(&*scratch_a), black_box(&*scratch_b)),
+            rng,
+        );
+    }
+
+    /// Compares `a` and `b` with a custom comparison function, for domain-specific comparisons
+    /// such as ordering parsed version numbers. `cmp` is called once for the result, then again on
+    /// each redundant pass to confirm it.
+    ///
+    /// Returns the ordering that the calls agreed on. If `cmp` returns an invalid
+    /// [`SecureOrdering`], or the calls disagree, the board securely resets itself.
+    #[inline(always)]
+    pub fn critical_compare_by<T, F: Fn(&T, &T) -> SecureOrdering>(
+        &self,
+        a: &T,
+        b: &T,
+        cmp: F,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureOrdering {
+        let ordering = black_box(cmp(black_box(a), black_box(b)));
+
+        self.critical_assert(
+            || black_box(ordering).is_valid() && black_box(cmp(a, b)) == black_box(ordering),
+            rng,
+        );
+
+        ordering
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::mock;
+    use crate::test_util::{resets, TestRng};
+    use core::cell::{Cell, RefCell};
+
+    /// Builds a signature block with `padding_len` bytes of padding followed by `payload`.
+    fn signature_block(padding_len: usize, payload: &[u8]) -> std::vec::Vec<u8> {
+        let mut em = std::vec![0x00, 0x01];
+        em.resize(em.len() + padding_len, 0xFF);
+        em.push(0x00);
+        em.extend_from_slice(payload);
+        em
+    }
+
+    #[test]
+    fn critical_check_padding_accepts_well_formed_padding() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        for em in [
+            signature_block(PKCS1_MIN_PADDING_LEN, b"digest"),
+            signature_block(200, b"digest"),
+            signature_block(PKCS1_MIN_PADDING_LEN, b""),
+        ] {
+            assert!(fip.critical_check_padding(&em, &mut rng) == SecureBool::True);
+        }
+    }
+
+    #[test]
+    fn critical_check_padding_rejects_malformed_padding() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+        let well_formed = signature_block(16, b"digest");
+
+        let mut malformed = std::vec![
+            signature_block(PKCS1_MIN_PADDING_LEN - 1, b"digest"),
+            well_formed[..10].to_vec(),
+            std::vec![0xFF; 40],
+        ];
+
+        for (index, byte) in [(0, 0x01), (1, 0x02), (5, 0xFE), (17, 0x01), (18, 0xFF)] {
+            let mut em = well_formed.clone();
+            em[index] = byte;
+            malformed.push(em);
+        }
+
+        // No separator.
+        malformed.push(std::vec![
+            0x00, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF
+        ]);
+
+        for em in malformed {
+            assert!(
+                fip.critical_check_padding(&em, &mut rng) == SecureBool::False,
+                "{em:02x?}"
+            );
+        }
+    }
+
+    #[test]
+    fn critical_memcmp_prefix_compares_prefix() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        for (haystack, needle, matches) in [
+            (&b"field\0\0\0"[..], &b"field"[..], SecureBool::True),
+            (b"field", b"field", SecureBool::True),
+            (b"field", b"", SecureBool::True),
+            (b"filed\0\0\0", b"field", SecureBool::False),
+            (b"field\0\0\0", b"fielD", SecureBool::False),
+            (b"fiel", b"field", SecureBool::False),
+            (b"", b"f", SecureBool::False),
+        ] {
+            assert!(fip.critical_memcmp_prefix(haystack, needle, &mut rng) == matches);
+        }
+    }
+
+    const SECRET: &[u8] = b"\x13\x37\xC0\xDE";
+
+    #[test]
+    fn critical_ct_eq_secret_accepts_correct_secret() {
+        let fip = FaultInjectionPrevention::new();
+
+        assert!(fip.critical_ct_eq_secret(SECRET, SECRET, &mut TestRng(1)) == SecureBool::True);
+    }
+
+    #[test]
+    fn critical_ct_eq_secret_rejects_wrong_secret() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        for candidate in [b"\x13\x37\xC0\xDF", b"\x12\x37\xC0\xDE", b"\0\0\0\0"] {
+            assert!(fip.critical_ct_eq_secret(SECRET, candidate, &mut rng) == SecureBool::False);
+        }
+    }
+
+    #[test]
+    fn critical_ct_eq_secret_resets_on_wrong_length_candidate() {
+        let fip = FaultInjectionPrevention::new();
+
+        for candidate in [&SECRET[..3], b"\x13\x37\xC0\xDE\0", b""] {
+            assert!(resets(|| {
+                fip.critical_ct_eq_secret(SECRET, candidate, &mut TestRng(1));
+            }));
+        }
+    }
+
+    #[test]
+    fn critical_verify_challenge_accepts_bound_response() {
+        let fip = FaultInjectionPrevention::new();
+        let response = bind_to_challenge(b"challenge A", b"secret");
+
+        let verified =
+            fip.critical_verify_challenge(b"challenge A", &response, b"secret", &mut TestRng(1));
+        assert!(verified == SecureBool::True);
+    }
+
+    #[test]
+    fn critical_verify_challenge_binds_with_length_prefixed_sha256() {
+        let mut hasher = Sha256::new();
+        hasher.update(11u64.to_le_bytes());
+        hasher.update(b"challenge A");
+        hasher.update(b"secret");
+        let expected: [u8; 32] = hasher.finalize().into();
+
+        assert_eq!(bind_to_challenge(b"challenge A", b"secret"), expected);
+        // Moving the boundary between the challenge and the expected value changes the binding.
+        assert_ne!(
+            bind_to_challenge(b"challenge As", b"ecret"),
+            bind_to_challenge(b"challenge A", b"secret")
+        );
+    }
+
+    #[test]
+    fn critical_verify_challenge_rejects_response_for_other_challenge() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+        let response_a = bind_to_challenge(b"challenge A", b"secret");
+
+        for (challenge, response, expected) in [
+            (&b"challenge B"[..], &response_a[..], &b"secret"[..]),
+            (b"challenge A", &response_a, b"secreT"),
+            (b"challenge A", &response_a[..31], b"secret"),
+            (b"challenge A", b"secret", b"secret"),
+        ] {
+            let verified = fip.critical_verify_challenge(challenge, response, expected, &mut rng);
+            assert!(verified == SecureBool::False);
+        }
+    }
+
+    #[test]
+    fn critical_verify_challenge_with_uses_custom_binding() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+        // Binds with the expected value first, unlike the default binding
+        let bind = |challenge: &[u8], expected: &[u8]| bind_to_challenge(expected, challenge);
+        let response = bind(b"challenge A", b"secret");
+
+        assert!(
+            fip.critical_verify_challenge_with(
+                b"challenge A",
+                &response,
+                b"secret",
+                bind,
+                &mut rng
+            ) == SecureBool::True
+        );
+        assert!(
+            fip.critical_verify_challenge_with(
+                b"challenge B",
+                &response,
+                b"secret",
+                bind,
+                &mut rng
+            ) == SecureBool::False
+        );
+        assert!(
+            fip.critical_verify_challenge(b"challenge A", &response, b"secret", &mut rng)
+                == SecureBool::False
+        );
+    }
+
+    #[test]
+    fn critical_verify_challenge_resets_when_binding_disagrees() {
+        let fip = FaultInjectionPrevention::new();
+        let calls = Cell::new(0u8);
+        let response = bind_to_challenge(b"challenge A", b"secret");
+        // Returns a different value on the redundant computation, as if it was glitched.
+        let glitched_bind = |challenge: &[u8], expected: &[u8]| {
+            calls.set(calls.get() + 1);
+            let mut bound = bind_to_challenge(challenge, expected);
+            bound[0] ^= calls.get() - 1;
+            bound
+        };
+
+        assert!(resets(|| {
+            fip.critical_verify_challenge_with(
+                b"challenge A",
+                &response,
+                b"secret",
+                glitched_bind,
+                &mut TestRng(1),
+            );
+        }));
+    }
+
+    /// Runs `critical_mac_full()` over `msg` with a mock MAC that records the fed blocks, and
+    /// returns the MAC and the blocks, or `None` if the device reset.
+    fn mac_full(msg: &[u8]) -> Option<([u8; 16], std::vec::Vec<std::vec::Vec<u8>>)> {
+        let fip = FaultInjectionPrevention::new();
+        let blocks = RefCell::new(std::vec::Vec::new());
+        let mut mac = [0; 16];
+
+        let reset = resets(|| {
+            mac = fip.critical_mac_full(
+                msg,
+                |block| blocks.borrow_mut().push(block.to_vec()),
+                || [blocks.borrow().len() as u8; 16],
+                &mut TestRng(1),
+            );
+        });
+
+        (!reset).then(|| (mac, blocks.into_inner()))
+    }
+
+    #[test]
+    fn critical_mac_full_feeds_every_block() {
+        let msg: std::vec::Vec<u8> = (0..40).collect();
+        let (mac, blocks) = mac_full(&msg).unwrap();
+
+        assert_eq!(mac, [3; 16]);
+        assert_eq!(blocks, [&msg[..16], &msg[16..32], &msg[32..]]);
+        assert_eq!(mac_full(&[]).unwrap().0, [0; 16]);
+    }
+
+    #[test]
+    fn critical_mac_full_resets_when_final_block_is_dropped() {
+        let msg = [0xA5; 40];
+
+        // The loop sees a corrupted offset before the final block and skips it.
+        mock::change_source_after(2);
+        assert!(mac_full(&msg).is_none());
+    }
+
+    /// Writes each byte's index, XORed with its current value, into `buf`.
+    fn write_indices(buf: &mut [u8]) {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte ^= i as u8;
+        }
+    }
+
+    #[test]
+    fn critical_redundant_effect_keeps_verified_result() {
+        let fip = FaultInjectionPrevention::new();
+        let runs = Cell::new(0);
+        // Leftover bytes must not leak into either run.
+        let mut scratch_a = [0xFF; 8];
+        let mut scratch_b = [0x55; 8];
+
+        fip.critical_redundant_effect(
+            |buf| {
+                runs.set(runs.get() + 1);
+                write_indices(buf);
+            },
+            &mut scratch_a,
+            &mut scratch_b,
+            &mut TestRng(1),
+        );
+
+        assert_eq!(runs.get(), 2);
+        assert_eq!(scratch_a, [0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn critical_redundant_effect_resets_when_second_run_diverges() {
+        let fip = FaultInjectionPrevention::new();
+        let runs = Cell::new(0);
+
+        assert!(resets(|| {
+            fip.critical_redundant_effect(
+                |buf| {
+                    runs.set(runs.get() + 1);
+                    write_indices(buf);
+
+                    // The second run is glitched and drops its last byte.
+                    if runs.get() == 2 {
+                        buf[7] = 0;
+                    }
+                },
+                &mut [0; 8],
+                &mut [0; 8],
+                &mut TestRng(1),
+            );
+        }));
+    }
+
+    #[test]
+    fn critical_redundant_effect_resets_on_mismatched_buffers() {
+        let fip = FaultInjectionPrevention::new();
+
+        assert!(resets(|| {
+            fip.critical_redundant_effect(write_indices, &mut [0; 8], &mut [0; 7], &mut TestRng(1));
+        }));
+    }
+
+    /// A parsed firmware version, compared by major, then minor, then patch number.
+    struct Version {
+        major: u16,
+        minor: u16,
+        patch: u16,
+    }
+
+    /// Compares versions like semantic versioning does.
+    fn cmp_versions(a: &Version, b: &Version) -> SecureOrdering {
+        (a.major, a.minor, a.patch)
+            .cmp(&(b.major, b.minor, b.patch))
+            .into()
+    }
+
+    fn version(major: u16, minor: u16, patch: u16) -> Version {
+        Version {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    #[test]
+    fn critical_compare_by_returns_custom_ordering() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        for (a, b, ordering) in [
+            (version(1, 2, 3), version(1, 2, 3), SecureOrdering::Equal),
+            (version(1, 2, 3), version(1, 10, 0), SecureOrdering::Less),
+            (
+                version(2, 0, 0),
+                version(1, 99, 99),
+                SecureOrdering::Greater,
+            ),
+            (version(1, 2, 4), version(1, 2, 3), SecureOrdering::Greater),
+        ] {
+            assert!(fip.critical_compare_by(&a, &b, cmp_versions, &mut rng) == ordering);
+        }
+    }
+
+    #[test]
+    fn critical_compare_by_confirms_result_redundantly() {
+        let fip = FaultInjectionPrevention::new();
+        let calls = Cell::new(0);
+
+        fip.critical_compare_by(
+            &version(1, 0, 0),
+            &version(1, 0, 0),
+            |a, b| {
+                calls.set(calls.get() + 1);
+                cmp_versions(a, b)
+            },
+            &mut TestRng(1),
+        );
+
+        // Once for the result, then once for each of the 4 evaluations that confirm it
+        assert_eq!(calls.get(), 5);
+    }
+
+    #[test]
+    fn critical_compare_by_resets_on_non_deterministic_comparator() {
+        let fip = FaultInjectionPrevention::new();
+
+        // The comparator changes its result after flip_after calls, like a glitched comparison.
+        for flip_after in 1..5 {
+            let calls = Cell::new(0);
+
+            assert!(resets(|| {
+                fip.critical_compare_by(
+                    &version(1, 0, 0),
+                    &version(1, 0, 0),
+                    |a, b| {
+                        calls.set(calls.get() + 1);
+
+                        if calls.get() > flip_after {
+                            SecureOrdering::Less
+                        } else {
+                            cmp_versions(a, b)
+                        }
+                    },
+                    &mut TestRng(1),
+                );
+            }));
+        }
+    }
+
+    /// Hashes a log entry together with the hash of the previous entry.
+    fn chain_hash(prev_hash: &[u8], entry: &[u8]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(prev_hash);
+        hasher.update(entry);
+        hasher.finalize().into()
+    }
+
+    #[test]
+    fn critical_verify_chain_link_accepts_valid_links() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+        let mut prev_hash = [0; 32];
+
+        for entry in [&b"boot"[..], b"unlock", b"update 1.2.3"] {
+            let hash = chain_hash(&prev_hash, entry);
+
+            assert!(
+                fip.critical_verify_chain_link(&prev_hash, entry, &hash, chain_hash, &mut rng)
+                    == SecureBool::True
+            );
+            prev_hash = hash;
+        }
+    }
+
+    #[test]
+    fn critical_verify_chain_link_rejects_tampered_links() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+        let prev_hash = chain_hash(&[0; 32], b"boot");
+        let hash = chain_hash(&prev_hash, b"unlock");
+        let mut tampered_hash = hash;
+        tampered_hash[31] ^= 1;
+
+        for (prev_hash, entry, claimed_hash) in [
+            (&prev_hash, &b"unlocK"[..], &hash),
+            (&prev_hash, b"unlock", &tampered_hash),
+            (&[0; 32], b"unlock", &hash),
+        ] {
+            assert!(
+                fip.critical_verify_chain_link(
+                    prev_hash,
+                    entry,
+                    claimed_hash,
+                    chain_hash,
+                    &mut rng
+                ) == SecureBool::False
+            );
+        }
+    }
+
+    #[test]
+    fn critical_verify_chain_link_resets_when_hashes_disagree() {
+        let fip = FaultInjectionPrevention::new();
+        let prev_hash = [0; 32];
+        let hash = chain_hash(&prev_hash, b"boot");
+        let calls = Cell::new(0);
+
+        // The second computation is glitched.
+        assert!(resets(|| {
+            fip.critical_verify_chain_link(
+                &prev_hash,
+                b"boot",
+                &hash,
+                |prev_hash, entry| {
+                    calls.set(calls.get() + 1);
+                    let mut hash = chain_hash(prev_hash, entry);
+                    hash[0] ^= u8::from(calls.get() == 2);
+                    hash
+                },
+                &mut TestRng(1),
+            );
+        }));
+    }
+}
@@ -0,0 +1,266 @@
+//! Per-call configuration of the countermeasures of
+//! [`FaultInjectionPrevention::critical_if_cfg()`](crate::FaultInjectionPrevention::critical_if_cfg).
+
+use crate::{
+    config_checksum, FaultInjectionPrevention, RandomError, SECURE_DELAY_MAX_CYCLES,
+    SECURE_DELAY_MIN_CYCLES,
+};
+use core::ptr::read_volatile;
+
+/// Countermeasure parameters of a single critical if statement.
+#[derive(Clone, Copy)]
+pub(crate) struct CheckParams {
+    pub(crate) min_passes: usize,
+    pub(crate) max_passes: usize,
+    pub(crate) delay_min_cycles: u32,
+    pub(crate) delay_max_cycles: u32,
+    pub(crate) salt_max_ops: usize,
+}
+
+/// Countermeasure settings for a single call site, passed to
+/// [`FaultInjectionPrevention::critical_if_cfg()`] to harden or relax that call independently of
+/// the global configuration. For example, an unlock check can use many random passes and long
+/// delays, while a hot path uses none.
+///
+/// A checksum of the settings is kept and verified each time the config is used, so a glitch can't
+/// weaken it to the fastest setting.
+///
+/// ```ignore
+/// let hardened = CriticalConfig::new()
+///     .with_random_redundancy(4, 8)?
+///     .with_delay_cycles(100, 400)?
+///     .with_barrier_salting(8);
+///
+/// fip.critical_if_cfg(&hardened, || check_pin(), || unlock(), || (), &mut rng);
+/// ```
+pub struct CriticalConfig {
+    min_passes: usize,
+    max_passes: usize,
+    delay_min_cycles: u32,
+    delay_max_cycles: u32,
+    salt_max_ops: usize,
+    checksum: usize,
+}
+
+impl CriticalConfig {
+    /// Creates a config with the same settings as a default [`FaultInjectionPrevention`]: no
+    /// additional passes, a 10-50 cycle delay, and no salting.
+    pub fn new() -> Self {
+        let mut cfg = CriticalConfig {
+            min_passes: 0,
+            max_passes: 0,
+            delay_min_cycles: SECURE_DELAY_MIN_CYCLES,
+            delay_max_cycles: SECURE_DELAY_MAX_CYCLES,
+            salt_max_ops: 0,
+            checksum: 0,
+        };
+
+        cfg.update_checksum();
+        cfg
+    }
+
+    /// Re-evaluates the condition a random number of additional times within `[min, max]`, like
+    /// [`FaultInjectionPrevention::with_random_redundancy()`].
+    ///
+    /// Returns an error if `min` is greater than `max`, or `max` doesn't fit in a `u32`.
+    pub fn with_random_redundancy(mut self, min: usize, max: usize) -> Result<Self, RandomError> {
+        if min > max || u32::try_from(max).is_err() {
+            return Err(RandomError::InvalidRange);
+        }
+
+        self.min_passes = min;
+        self.max_passes = max;
+        self.update_checksum();
+        Ok(self)
+    }
+
+    /// Sets the cycle range of the random delay between the checks.
+    ///
+    /// Returns an error if `min_cycles` is greater than `max_cycles`.
+    pub fn with_delay_cycles(
+        mut self,
+        min_cycles: u32,
+        max_cycles: u32,
+    ) -> Result<Self, RandomError> {
+        if min_cycles > max_cycles {
+            return Err(RandomError::InvalidRange);
+        }
+
+        self.delay_min_cycles = min_cycles;
+        self.delay_max_cycles = max_cycles;
+        self.update_checksum();
+        Ok(self)
+    }
+
+    /// Interleaves the checks with up to `max_dummy_ops` dummy operations, like
+    /// [`FaultInjectionPrevention::with_barrier_salting()`].
+    pub fn with_barrier_salting(mut self, max_dummy_ops: u32) -> Self {
+        self.salt_max_ops = max_dummy_ops as usize;
+        self.update_checksum();
+        self
+    }
+
+    /// Reads the settings with volatile reads.
+    #[inline(always)]
+    pub(crate) fn params(&self) -> CheckParams {
+        // SAFETY: The fields are non-null, properly aligned, and initialized since they come from
+        // a Rust reference.
+        unsafe {
+            CheckParams {
+                min_passes: read_volatile(&self.min_passes),
+                max_passes: read_volatile(&self.max_passes),
+                delay_min_cycles: read_volatile(&self.delay_min_cycles),
+                delay_max_cycles: read_volatile(&self.delay_max_cycles),
+                salt_max_ops: read_volatile(&self.salt_max_ops),
+            }
+        }
+    }
+
+    /// Computes the checksum of the settings. Must include every setting.
+    #[inline(always)]
+    fn compute_checksum(&self) -> usize {
+        let params = self.params();
+
+        config_checksum(&[
+            params.min_passes,
+            params.max_passes,
+            params.delay_min_cycles as usize,
+            params.delay_max_cycles as usize,
+            params.salt_max_ops,
+        ])
+    }
+
+    /// Recomputes the stored checksum. Must be called after any change to the settings.
+    fn update_checksum(&mut self) {
+        self.checksum = self.compute_checksum();
+    }
+
+    /// Verifies that the settings haven't been corrupted since they were set.
+    ///
+    /// If the settings were corrupted, the board securely resets itself.
+    #[inline(always)]
+    pub(crate) fn verify(&self) {
+        FaultInjectionPrevention::verify_checksum(&self.checksum, || self.compute_checksum());
+    }
+}
+
+impl Default for CriticalConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{resets, TestRng};
+    use crate::SecureBool;
+    use core::cell::Cell;
+
+    /// Runs a `critical_if_cfg` on `value` with `cfg`, and returns which closure ran and how many
+    /// times the condition was evaluated.
+    fn run_with(
+        cfg: &CriticalConfig,
+        value: SecureBool,
+        rng: &mut TestRng,
+    ) -> (&'static str, usize) {
+        let fip = FaultInjectionPrevention::new();
+        let branch = Cell::new("none");
+        let evaluations = Cell::new(0);
+
+        fip.critical_if_cfg(
+            cfg,
+            || {
+                evaluations.set(evaluations.get() + 1);
+                value
+            },
+            || branch.set("success"),
+            || branch.set("failure"),
+            rng,
+        );
+
+        (branch.get(), evaluations.get())
+    }
+
+    #[test]
+    fn call_sites_use_their_own_config() {
+        let hardened = CriticalConfig::new()
+            .with_random_redundancy(4, 8)
+            .unwrap()
+            .with_delay_cycles(100, 400)
+            .unwrap()
+            .with_barrier_salting(8);
+        let fast = CriticalConfig::new().with_delay_cycles(0, 0).unwrap();
+        let mut rng = TestRng(1);
+
+        for _ in 0..50 {
+            let (branch, evaluations) = run_with(&hardened, SecureBool::True, &mut rng);
+            assert_eq!(branch, "success");
+            assert!((4 + 4..=4 + 8).contains(&evaluations));
+
+            let (branch, evaluations) = run_with(&hardened, SecureBool::False, &mut rng);
+            assert_eq!(branch, "failure");
+            assert!((3 + 4..=3 + 8).contains(&evaluations));
+
+            assert_eq!(run_with(&fast, SecureBool::True, &mut rng), ("success", 4));
+            assert_eq!(run_with(&fast, SecureBool::False, &mut rng), ("failure", 3));
+        }
+    }
+
+    #[test]
+    fn default_config_matches_global_configuration() {
+        let mut rng = TestRng(1);
+
+        assert_eq!(
+            run_with(&CriticalConfig::default(), SecureBool::True, &mut rng),
+            ("success", 4)
+        );
+        assert_eq!(
+            run_with(&CriticalConfig::default(), SecureBool::False, &mut rng),
+            ("failure", 3)
+        );
+    }
+
+    #[test]
+    fn config_rejects_invalid_ranges() {
+        assert!(CriticalConfig::new().with_random_redundancy(3, 2).is_err());
+        assert!(CriticalConfig::new()
+            .with_random_redundancy(0, u32::MAX as usize + 1)
+            .is_err());
+        assert!(CriticalConfig::new().with_delay_cycles(11, 10).is_err());
+    }
+
+    #[test]
+    fn critical_if_cfg_resets_on_corrupted_config() {
+        // Each setting is weakened without updating the checksum, like a glitch would.
+        let corruptions: [fn(&mut CriticalConfig); 5] = [
+            |cfg| cfg.min_passes = 0,
+            |cfg| cfg.max_passes = 0,
+            |cfg| cfg.delay_min_cycles = 0,
+            |cfg| cfg.delay_max_cycles = 0,
+            |cfg| cfg.salt_max_ops = 0,
+        ];
+
+        for corrupt in corruptions {
+            let mut cfg = CriticalConfig::new()
+                .with_random_redundancy(4, 8)
+                .unwrap()
+                .with_delay_cycles(100, 400)
+                .unwrap()
+                .with_barrier_salting(8);
+            let ran = Cell::new(false);
+
+            corrupt(&mut cfg);
+            assert!(resets(|| {
+                FaultInjectionPrevention::new().critical_if_cfg(
+                    &cfg,
+                    || SecureBool::True,
+                    || ran.set(true),
+                    || ran.set(true),
+                    &mut TestRng(1),
+                );
+            }));
+            assert!(!ran.get());
+        }
+    }
+}
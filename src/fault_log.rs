@@ -0,0 +1,158 @@
+//! Retained log of the address of the memory that failed verification before the last fault reset.
+
+use crate::FaultInjectionPrevention;
+#[cfg(test)]
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+#[cfg(not(test))]
+use core::ptr::addr_of_mut;
+use core::ptr::{read_volatile, write_volatile};
+
+// Marks the log as holding a valid address, since retained RAM is uninitialized after power-on
+const FAULT_LOG_MAGIC: u32 = 0xFA17_AD0C;
+
+/// A recorded fault address. The address is stored alongside its complement so a partially
+/// written or corrupted entry isn't mistaken for a valid one.
+#[derive(Clone, Copy)]
+struct FaultAddressLog {
+    magic: u32,
+    address: usize,
+    address_complement: usize,
+}
+
+// Placed in `.uninit` so it isn't zeroed by the runtime and survives a reset.
+#[cfg(not(test))]
+#[link_section = ".uninit.fip_fault_address_log"]
+static mut FAULT_ADDRESS_LOG: MaybeUninit<FaultAddressLog> = MaybeUninit::uninit();
+
+// Unit tests run in parallel, so each test thread gets its own log.
+#[cfg(test)]
+std::thread_local! {
+    static FAULT_ADDRESS_LOG: UnsafeCell<MaybeUninit<FaultAddressLog>> =
+        const { UnsafeCell::new(MaybeUninit::zeroed()) };
+}
+
+/// Returns a pointer to the retained fault address log.
+#[cfg(not(test))]
+#[inline(always)]
+fn fault_address_log() -> *mut FaultAddressLog {
+    addr_of_mut!(FAULT_ADDRESS_LOG).cast()
+}
+
+/// Returns a pointer to the fault address log of the current test thread.
+#[cfg(test)]
+#[inline(always)]
+fn fault_address_log() -> *mut FaultAddressLog {
+    FAULT_ADDRESS_LOG.with(|log| log.get().cast())
+}
+
+/// Records the address of the memory that failed verification.
+#[inline(always)]
+pub(crate) fn record(address: usize) {
+    // SAFETY: No race conditions because this library only supports single threaded programs.
+    // FAULT_ADDRESS_LOG is a static, so it's non-null and properly aligned.
+    unsafe {
+        write_volatile(
+            fault_address_log(),
+            FaultAddressLog {
+                magic: FAULT_LOG_MAGIC,
+                address,
+                address_complement: !address,
+            },
+        );
+    }
+}
+
+impl FaultInjectionPrevention {
+    /// Returns the address of the memory that failed verification in
+    /// [`FaultInjectionPrevention::critical_read()`] or
+    /// [`FaultInjectionPrevention::critical_write()`] before the last fault reset, and clears the
+    /// log. Returns `None` if no fault address has been recorded since the log was last cleared.
+    ///
+    /// The log is kept in the `.uninit` section, so the linker script must place that section in
+    /// RAM that is retained across resets.
+    pub fn take_fault_address() -> Option<usize> {
+        // SAFETY: No race conditions because this library only supports single threaded programs.
+        // FAULT_ADDRESS_LOG is a static, so it's non-null and properly aligned. The magic value and
+        // complement are checked before the address is trusted.
+        let log = unsafe { read_volatile(fault_address_log()) };
+
+        // SAFETY: Same as above.
+        unsafe {
+            write_volatile(
+                fault_address_log(),
+                FaultAddressLog {
+                    magic: 0,
+                    address: 0,
+                    address_complement: 0,
+                },
+            );
+        }
+
+        if log.magic == FAULT_LOG_MAGIC && log.address == !log.address_complement {
+            Some(log.address)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::mock;
+    use crate::test_util::{resets, TestRng};
+
+    // Recognizable value that must not end up in the log
+    const SECRET: u32 = 0x5EC2_E7AB;
+
+    /// Returns whether the raw bytes of the log contain `value` anywhere.
+    fn log_contains(value: u32) -> bool {
+        // SAFETY: The log of this thread is initialized, properly aligned, and not borrowed.
+        let log = unsafe { read_volatile(fault_address_log()) };
+        let words = [log.magic as usize, log.address, log.address_complement];
+        let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_ne_bytes()).collect();
+
+        bytes.windows(4).any(|window| window == value.to_ne_bytes())
+    }
+
+    #[test]
+    fn take_fault_address_is_none_without_fault() {
+        assert_eq!(FaultInjectionPrevention::take_fault_address(), None);
+    }
+
+    #[test]
+    fn critical_read_records_source_address_on_mismatch() {
+        let fip = FaultInjectionPrevention::new();
+        let src = SECRET;
+
+        mock::change_source_after(3);
+        assert!(resets(|| {
+            fip.critical_read(&src, &mut TestRng(1));
+        }));
+
+        assert!(!log_contains(SECRET) && !log_contains(!SECRET));
+        assert_eq!(
+            FaultInjectionPrevention::take_fault_address(),
+            Some(&src as *const u32 as usize)
+        );
+        assert_eq!(FaultInjectionPrevention::take_fault_address(), None);
+    }
+
+    #[test]
+    fn critical_write_records_destination_address_on_mismatch() {
+        let fip = FaultInjectionPrevention::new();
+        let mut dst: u32 = 0;
+        let dst_addr = &dst as *const u32 as usize;
+
+        assert!(resets(|| {
+            fip.critical_write(&mut dst, SECRET, |_, _| (), &mut TestRng(1));
+        }));
+
+        assert!(!log_contains(SECRET));
+        assert_eq!(
+            FaultInjectionPrevention::take_fault_address(),
+            Some(dst_addr)
+        );
+    }
+}
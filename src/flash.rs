@@ -0,0 +1,479 @@
+//! Fault-injection resistant writes to non-volatile memory.
+
+use crate::platform::{Arch, Platform};
+use crate::{helper, FaultInjectionPrevention, SecureBool};
+use core::hint::black_box;
+use core::ptr::read_volatile;
+use rand_core::CryptoRngCore;
+
+#[cfg(feature = "estorage")]
+use embedded_storage::nor_flash::NorFlash;
+
+// Size of the stack buffer used to read back data written through `NorFlash`
+#[cfg(feature = "estorage")]
+const NVM_READBACK_CHUNK_LEN: usize = 32;
+
+impl FaultInjectionPrevention {
+    /// Same as [`FaultInjectionPrevention::critical_write()`], but after a random delay, re-reads
+    /// `dst` and verifies that the value persisted. This catches time-shifted flash glitches, like
+    /// a delayed erase that reverts a just-written protection word after the immediate read-back
+    /// already passed.
+    ///
+    /// If a fault injection is detected, the board securely resets itself.
+    #[inline(always)]
+    pub fn critical_write_flash_persistent<T>(
+        &self,
+        dst: &mut T,
+        src: T,
+        write_op: impl FnMut(&mut T, T),
+        rng: &mut impl CryptoRngCore,
+    ) where
+        T: Eq + Copy + Default,
+    {
+        let dst_addr = dst as *const T as usize;
+
+        self.critical_write(dst, src, write_op, rng);
+
+        helper::dsb();
+
+        self.secure_random_delay(rng);
+
+        // SAFETY: dst is valid for reads, properly initialized, and properly aligned because it
+        // is a Rust reference.
+        self.critical_if(
+            || unsafe {
+                (Arch::read_source(black_box(&*dst)) == read_volatile(black_box(&src))).into()
+            },
+            || (),
+            || Self::secure_reset_device_at(dst_addr),
+            rng,
+        );
+    }
+
+    /// Sets a one-shot security flag in flash exactly once, for anti-rollback and one-time
+    /// provisioning. `read_fn` returns whether the flag is currently set, and `write_fn` sets it.
+    /// The flag is redundantly checked to be unset before writing, then re-read after a random
+    /// delay to confirm that the write stuck and wasn't reverted.
+    ///
+    /// Returns [`SecureBool::True`] if this call newly set the flag, and [`SecureBool::False`] if
+    /// it was already set. If `read_fn` returns [`SecureBool::Error`], the flag doesn't read as set
+    /// after the write, or the redundant checks disagree, the board securely resets itself.
+    #[inline(always)]
+    pub fn critical_burn_once(
+        &self,
+        read_fn: impl Fn() -> SecureBool,
+        write_fn: impl FnOnce(),
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        let initial = black_box(read_fn());
+        self.critical_assert(|| black_box(initial) != SecureBool::Error, rng);
+
+        let already_burned =
+            self.critical_bool(|| (black_box(read_fn()) == SecureBool::True).into(), rng);
+
+        if black_box(already_burned) == SecureBool::True {
+            // Re-check so a glitch on the branch above can't skip the burn.
+            self.critical_assert(|| black_box(read_fn()) == SecureBool::True, rng);
+            return SecureBool::False;
+        }
+
+        helper::dsb();
+        write_fn();
+        helper::dsb();
+
+        self.critical_assert(|| black_box(read_fn()) == SecureBool::True, rng);
+
+        // Re-read after a delay to catch a delayed revert of the write.
+        self.secure_random_delay(rng);
+        self.critical_assert(|| black_box(read_fn()) == SecureBool::True, rng);
+
+        self.critical_bool(
+            || (black_box(already_burned) == SecureBool::False).into(),
+            rng,
+        )
+    }
+
+    /// Verifies that a downloaded chunk, e.g. during an OTA update, lies entirely within the
+    /// writable flash region before it is written, so a glitched offset or length can't overwrite
+    /// the bootloader. The chunk covers `[offset, offset + len)` and the region covers
+    /// `[writable_lo, writable_hi)`.
+    ///
+    /// Returns [`SecureBool::True`] if the chunk is within the region, and [`SecureBool::False`]
+    /// otherwise, including if `offset + len` overflows. If the redundant checks disagree, the
+    /// board securely resets itself.
+    #[inline(always)]
+    pub fn critical_check_ota_chunk(
+        &self,
+        offset: u32,
+        len: u32,
+        writable_lo: u32,
+        writable_hi: u32,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        self.critical_bool(
+            || {
+                let offset = black_box(offset);
+                let len = black_box(len);
+                let lo = black_box(writable_lo);
+                let hi = black_box(writable_hi);
+
+                // Check both the end address and the space left in the region, so a single
+                // glitched comparison isn't enough.
+                let end_in_range = offset.checked_add(len).is_some_and(|end| end <= hi);
+                let len_fits = hi.checked_sub(offset).is_some_and(|room| len <= room);
+
+                (offset >= lo && black_box(end_in_range) && black_box(len_fits)).into()
+            },
+            rng,
+        )
+    }
+
+    /// Reads back `data.len()` bytes at `offset` from `storage` in chunks, and compares them
+    /// against `data` in constant time.
+    #[cfg(feature = "estorage")]
+    #[inline(always)]
+    fn nvm_matches<S: NorFlash>(
+        storage: &mut S,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<bool, S::Error> {
+        let mut readback = [0u8; NVM_READBACK_CHUNK_LEN];
+        let mut matches = true;
+        let mut chunk_offset = offset;
+
+        for chunk in data.chunks(NVM_READBACK_CHUNK_LEN) {
+            let readback = &mut readback[..chunk.len()];
+
+            storage.read(chunk_offset, readback)?;
+            matches &= helper::ct_eq_slices(black_box(readback), chunk);
+
+            chunk_offset += chunk.len() as u32;
+        }
+
+        Ok(black_box(matches))
+    }
+
+    /// Writes `data` at `offset` to an `embedded-storage` [`NorFlash`], then reads it back through
+    /// the trait to verify it, and re-verifies it after a random delay to catch a delayed revert.
+    /// The region must already be erased, and `offset` and `data` must meet the alignment
+    /// requirements of `storage`.
+    ///
+    /// Returns an error if `storage` fails to write or read. If the data read back doesn't match
+    /// `data`, the board securely resets itself.
+    #[cfg(feature = "estorage")]
+    #[inline(always)]
+    pub fn critical_nvm_write<S: NorFlash>(
+        &self,
+        storage: &mut S,
+        offset: u32,
+        data: &[u8],
+        rng: &mut impl CryptoRngCore,
+    ) -> Result<(), S::Error> {
+        helper::dsb();
+        storage.write(black_box(offset), black_box(data))?;
+        helper::dsb();
+
+        let written = Self::nvm_matches(storage, offset, data)?;
+        self.critical_assert(|| black_box(written), rng);
+
+        self.secure_random_delay(rng);
+
+        let persisted = Self::nvm_matches(storage, offset, data)?;
+        self.critical_assert(|| black_box(persisted), rng);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::mock;
+    use crate::test_util::{resets, TestRng};
+    use core::cell::Cell;
+
+    // Reads of the destination by the immediate read-back of critical_write(): 3 writes, each
+    // verified by a critical_if that evaluates its condition 4 times
+    const READ_BACKS: usize = 12;
+
+    /// Writes to a mock flash word like a flash driver would.
+    fn write_flash(dst: &mut u32, src: u32) {
+        *dst = src;
+    }
+
+    #[test]
+    fn critical_write_flash_persistent_writes_value() {
+        let fip = FaultInjectionPrevention::new();
+        let mut flash: u32 = 0xFFFF_FFFF;
+
+        fip.critical_write_flash_persistent(&mut flash, 0x1234_5678, write_flash, &mut TestRng(1));
+
+        assert_eq!(flash, 0x1234_5678);
+    }
+
+    #[test]
+    fn critical_write_flash_persistent_resets_on_revert_after_read_back() {
+        let fip = FaultInjectionPrevention::new();
+
+        // The mock flash reverts right after the immediate read-back, which critical_write()
+        // alone doesn't catch.
+        mock::change_source_after(READ_BACKS);
+        let mut flash: u32 = 0xFFFF_FFFF;
+        fip.critical_write(&mut flash, 0x1234_5678, write_flash, &mut TestRng(1));
+
+        mock::change_source_after(READ_BACKS);
+        assert!(resets(|| {
+            let mut flash: u32 = 0xFFFF_FFFF;
+            fip.critical_write_flash_persistent(
+                &mut flash,
+                0x1234_5678,
+                write_flash,
+                &mut TestRng(1),
+            );
+        }));
+    }
+
+    #[test]
+    fn critical_write_flash_persistent_resets_on_revert_during_read_back() {
+        let fip = FaultInjectionPrevention::new();
+
+        mock::change_source_after(READ_BACKS - 1);
+        assert!(resets(|| {
+            let mut flash: u32 = 0xFFFF_FFFF;
+            fip.critical_write_flash_persistent(
+                &mut flash,
+                0x1234_5678,
+                write_flash,
+                &mut TestRng(1),
+            );
+        }));
+    }
+
+    /// Burns a mock one-shot flag with `write_fn`, and returns the result and whether the flag is
+    /// set afterwards, or `None` if the device reset.
+    fn burn_once(
+        flag: &Cell<SecureBool>,
+        write_fn: impl FnOnce(),
+    ) -> Option<(SecureBool, SecureBool)> {
+        let fip = FaultInjectionPrevention::new();
+        let mut result = SecureBool::Error;
+
+        let reset = resets(|| {
+            result = fip.critical_burn_once(|| flag.get(), write_fn, &mut TestRng(1));
+        });
+
+        (!reset).then(|| (result, flag.get()))
+    }
+
+    #[test]
+    fn critical_burn_once_burns_unset_flag() {
+        let flag = Cell::new(SecureBool::False);
+        let result = burn_once(&flag, || flag.set(SecureBool::True));
+
+        assert!(result == Some((SecureBool::True, SecureBool::True)));
+    }
+
+    #[test]
+    fn critical_burn_once_doesnt_burn_set_flag_again() {
+        let flag = Cell::new(SecureBool::True);
+        let writes = Cell::new(0);
+        let result = burn_once(&flag, || writes.set(writes.get() + 1));
+
+        assert!(result == Some((SecureBool::False, SecureBool::True)));
+        assert_eq!(writes.get(), 0);
+    }
+
+    #[test]
+    fn critical_burn_once_resets_on_faulted_write() {
+        let flag = Cell::new(SecureBool::False);
+
+        assert!(burn_once(&flag, || ()).is_none());
+    }
+
+    #[test]
+    fn critical_burn_once_resets_on_read_error() {
+        let flag = Cell::new(SecureBool::Error);
+
+        assert!(burn_once(&flag, || flag.set(SecureBool::True)).is_none());
+    }
+
+    /// Checks a chunk against a writable region of `0x1000..0x2000`.
+    fn check_chunk(offset: u32, len: u32) -> SecureBool {
+        FaultInjectionPrevention::new().critical_check_ota_chunk(
+            offset,
+            len,
+            0x1000,
+            0x2000,
+            &mut TestRng(1),
+        )
+    }
+
+    #[test]
+    fn critical_check_ota_chunk_accepts_chunks_in_range() {
+        assert!(check_chunk(0x1000, 0x100) == SecureBool::True);
+        assert!(check_chunk(0x1F00, 0x100) == SecureBool::True);
+        assert!(check_chunk(0x1000, 0x1000) == SecureBool::True);
+        assert!(check_chunk(0x1800, 0) == SecureBool::True);
+    }
+
+    #[test]
+    fn critical_check_ota_chunk_rejects_straddling_chunks() {
+        assert!(check_chunk(0x0F00, 0x200) == SecureBool::False);
+        assert!(check_chunk(0x1F00, 0x200) == SecureBool::False);
+        assert!(check_chunk(0x0F00, 0x1200) == SecureBool::False);
+    }
+
+    #[test]
+    fn critical_check_ota_chunk_rejects_overflowing_chunks() {
+        assert!(check_chunk(0x1800, u32::MAX) == SecureBool::False);
+        assert!(check_chunk(u32::MAX, 0x1001) == SecureBool::False);
+    }
+
+    #[test]
+    fn critical_check_ota_chunk_rejects_chunks_out_of_range() {
+        assert!(check_chunk(0, 0x100) == SecureBool::False);
+        assert!(check_chunk(0x2000, 0x100) == SecureBool::False);
+        assert!(check_chunk(0x3000, 0) == SecureBool::False);
+    }
+
+    /// Mock `NorFlash` with optional faults, using NOR semantics: writes can only clear bits.
+    #[cfg(feature = "estorage")]
+    struct MockFlash {
+        data: [u8; 128],
+        // Byte that a write leaves unchanged, like a glitched write
+        skipped_byte: Option<usize>,
+        // Number of successful reads until the flash reverts to erased, like a delayed erase
+        reads_until_revert: Option<usize>,
+    }
+
+    #[cfg(feature = "estorage")]
+    impl MockFlash {
+        fn new() -> Self {
+            MockFlash {
+                data: [0xFF; 128],
+                skipped_byte: None,
+                reads_until_revert: None,
+            }
+        }
+    }
+
+    #[cfg(feature = "estorage")]
+    impl embedded_storage::nor_flash::ErrorType for MockFlash {
+        type Error = embedded_storage::nor_flash::NorFlashErrorKind;
+    }
+
+    #[cfg(feature = "estorage")]
+    impl embedded_storage::nor_flash::ReadNorFlash for MockFlash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            embedded_storage::nor_flash::check_read(self, offset, bytes.len())?;
+
+            match self.reads_until_revert {
+                Some(0) => self.data = [0xFF; 128],
+                Some(reads) => self.reads_until_revert = Some(reads - 1),
+                None => {}
+            }
+
+            let offset = offset as usize;
+            bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.data.len()
+        }
+    }
+
+    #[cfg(feature = "estorage")]
+    impl NorFlash for MockFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = 64;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            embedded_storage::nor_flash::check_erase(self, from, to)?;
+
+            self.data[from as usize..to as usize].fill(0xFF);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            embedded_storage::nor_flash::check_write(self, offset, bytes.len())?;
+
+            for (i, &byte) in bytes.iter().enumerate() {
+                let address = offset as usize + i;
+
+                if self.skipped_byte != Some(address) {
+                    self.data[address] &= byte;
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "estorage")]
+    #[test]
+    fn critical_nvm_write_writes_and_verifies_data() {
+        let fip = FaultInjectionPrevention::new();
+        let mut flash = MockFlash::new();
+        // Longer than the read-back chunk, so the data is verified in several chunks
+        let data: Vec<u8> = (0..40).collect();
+
+        assert!(fip
+            .critical_nvm_write(&mut flash, 8, &data, &mut TestRng(1))
+            .is_ok());
+        assert_eq!(&flash.data[8..48], &data[..]);
+    }
+
+    #[cfg(feature = "estorage")]
+    #[test]
+    fn critical_nvm_write_returns_storage_errors() {
+        let fip = FaultInjectionPrevention::new();
+        let mut flash = MockFlash::new();
+
+        // Misaligned, and out of bounds
+        assert!(fip
+            .critical_nvm_write(&mut flash, 2, &[0; 4], &mut TestRng(1))
+            .is_err());
+        assert!(fip
+            .critical_nvm_write(&mut flash, 128, &[0; 4], &mut TestRng(1))
+            .is_err());
+    }
+
+    #[cfg(feature = "estorage")]
+    #[test]
+    fn critical_nvm_write_resets_on_faulty_write() {
+        let fip = FaultInjectionPrevention::new();
+        let mut flash = MockFlash::new();
+        flash.skipped_byte = Some(37);
+
+        assert!(resets(|| {
+            fip.critical_nvm_write(&mut flash, 32, &[0x5A; 8], &mut TestRng(1))
+                .ok();
+        }));
+
+        // Writing over unerased data can't set bits back.
+        let mut flash = MockFlash::new();
+        flash.data[0] = 0x00;
+
+        assert!(resets(|| {
+            fip.critical_nvm_write(&mut flash, 0, &[0x5A; 4], &mut TestRng(1))
+                .ok();
+        }));
+    }
+
+    #[cfg(feature = "estorage")]
+    #[test]
+    fn critical_nvm_write_resets_on_delayed_revert() {
+        let fip = FaultInjectionPrevention::new();
+        let mut flash = MockFlash::new();
+        // Reverts after the immediate read-back of the single chunk
+        flash.reads_until_revert = Some(1);
+
+        assert!(resets(|| {
+            fip.critical_nvm_write(&mut flash, 0, &[0x5A; 8], &mut TestRng(1))
+                .ok();
+        }));
+    }
+}
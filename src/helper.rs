@@ -1,14 +1,33 @@
-use core::arch::asm;
-use core::sync::atomic::{compiler_fence, Ordering};
+use crate::platform::{Arch, Platform};
+use core::hint::black_box;
 
 /// DSB with compiler fence.
-// https://github.com/rust-embedded/cortex-m/issues/308
 #[inline(always)]
 pub(crate) fn dsb() {
-    compiler_fence(Ordering::SeqCst);
+    Arch::dsb();
+}
+
+/// Constant-time byte equality. Returns 1 if `a == b`, and 0 otherwise.
+#[inline(always)]
+pub(crate) fn ct_eq_u8(a: u8, b: u8) -> u8 {
+    let diff = u16::from(a ^ b);
+
+    // Only a difference of 0 borrows into the upper byte.
+    (diff.wrapping_sub(1) >> 8) as u8 & 1
+}
+
+/// Constant-time equality of two equal-length byte slices. Returns false if the lengths differ.
+#[inline(always)]
+pub(crate) fn ct_eq_slices(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut diff: u8 = 0;
 
-    // SAFETY: "dsb" is always safe.
-    unsafe { asm!("dsb") }
+    for (&x, &y) in a.iter().zip(b.iter()) {
+        diff |= black_box(x ^ y);
+    }
 
-    compiler_fence(Ordering::SeqCst);
+    black_box(diff) == 0
 }
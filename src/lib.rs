@@ -3,21 +3,54 @@
 //! attack is detected, the device will reset.
 
 #![warn(missing_docs)]
-#![no_std]
-
+#![cfg_attr(not(test), no_std)]
+
+mod boot;
+mod checks;
+mod compare;
+mod config;
+#[cfg(feature = "fault_address_log")]
+mod fault_log;
+mod flash;
 mod helper;
-
+mod platform;
+mod registers;
+mod secure_enum;
+#[cfg(test)]
+mod test_util;
+mod unlock;
+mod zeroize;
+
+use config::CheckParams;
+#[cfg(target_arch = "arm")]
 use core::arch::asm;
+use core::cell::Cell;
+#[cfg(test)]
+use core::cell::UnsafeCell;
 use core::hint::black_box;
+use core::ops::Deref;
+#[cfg(target_os = "none")]
 use core::panic::PanicInfo;
+#[cfg(not(test))]
+use core::ptr::{addr_of, addr_of_mut};
 use core::ptr::{read_volatile, write_volatile};
 use core::result::Result;
-use cortex_m::asm::delay;
+use platform::{Arch, Platform};
 use rand_core::CryptoRngCore;
 use sealed::sealed;
 
 extern crate const_random;
 
+pub use boot::BootSlot;
+pub use compare::SecureOrdering;
+pub use config::CriticalConfig;
+#[doc(hidden)]
+pub use const_random::const_random as __const_random;
+#[doc(hidden)]
+pub use secure_enum::secure_enum_discriminant as __secure_enum_discriminant;
+pub use secure_enum::SecureEnum;
+pub use unlock::UnlockSequence;
+
 /// Global stack that pushes new stack canaries onto non-stack memory
 struct RefCanaryStack {
     reference_canary_vec: [u64; 50],
@@ -42,6 +75,8 @@ impl RefCanaryStack {
         fip: &FaultInjectionPrevention,
         rng: &mut impl CryptoRngCore,
     ) {
+        self.verify_base(fip, rng);
+
         if self.counter >= self.reference_canary_vec.len() - 1 {
             panic!()
         }
@@ -75,6 +110,8 @@ impl RefCanaryStack {
     /// the actual stack canary value with the reference canary value
     #[inline(always)]
     fn pop(&mut self, fip: &FaultInjectionPrevention, rng: &mut impl CryptoRngCore) -> u64 {
+        self.verify_base(fip, rng);
+
         let popped_value = self.reference_canary_vec[self.counter];
 
         // need extra variable to because `self.counter` is mutably borrowed
@@ -98,23 +135,139 @@ impl RefCanaryStack {
     fn peek(&self) -> u64 {
         self.reference_canary_vec[self.counter]
     }
+
+    /// Verifies that this is the canary stack at the address recorded in `REF_CANARY_BASE`, so a
+    /// corrupted or glitched pointer to the canary stack can't redirect the canaries to other
+    /// memory.
+    ///
+    /// If the address doesn't match, the board securely resets itself.
+    #[inline(always)]
+    fn verify_base(&self, fip: &FaultInjectionPrevention, rng: &mut impl CryptoRngCore) {
+        let expected = ref_canary_base() as usize;
+
+        fip.critical_assert(
+            || black_box(self as *const Self as usize) == black_box(expected),
+            rng,
+        );
+    }
 }
 
+#[cfg(not(test))]
 static mut REF_CANARY: RefCanaryStack = RefCanaryStack::new();
 
-// Application Interrupt and Reset Control Register
-const AIRCR_ADDR: u32 = 0xE000ED0C;
-const AIRCR_VECTKEY: u32 = 0x05FA << 16;
-const AIRCR_SYSRESETREQ: u32 = 1 << 2;
+// Unit tests run in parallel, so each test thread gets its own canary stack.
+#[cfg(test)]
+std::thread_local! {
+    static REF_CANARY: UnsafeCell<RefCanaryStack> = const { UnsafeCell::new(RefCanaryStack::new()) };
+}
+
+/// Address of `REF_CANARY` recorded at link time.
+#[cfg(not(test))]
+struct CanaryBase(*const RefCanaryStack);
+
+// SAFETY: The pointer is never dereferenced through CanaryBase, only compared.
+#[cfg(not(test))]
+unsafe impl Sync for CanaryBase {}
+
+#[cfg(not(test))]
+static REF_CANARY_BASE: CanaryBase = CanaryBase(addr_of!(REF_CANARY));
+
+/// Returns a pointer to the global canary stack.
+#[cfg(not(test))]
+#[inline(always)]
+fn ref_canary() -> *mut RefCanaryStack {
+    addr_of_mut!(REF_CANARY)
+}
+
+/// Returns a pointer to the canary stack of the current test thread.
+#[cfg(test)]
+#[inline(always)]
+fn ref_canary() -> *mut RefCanaryStack {
+    REF_CANARY.with(UnsafeCell::get)
+}
+
+/// Returns the address of the global canary stack recorded in `REF_CANARY_BASE`.
+#[cfg(not(test))]
+#[inline(always)]
+fn ref_canary_base() -> *const RefCanaryStack {
+    // SAFETY: REF_CANARY_BASE is an immutable static, so it's non-null, properly aligned, and
+    // initialized.
+    unsafe { read_volatile(addr_of!(REF_CANARY_BASE.0)) }
+}
+
+/// Returns the address of the canary stack of the current test thread.
+#[cfg(test)]
+#[inline(always)]
+fn ref_canary_base() -> *const RefCanaryStack {
+    ref_canary()
+}
+
+// Default number of times redundant security checks are repeated
+const DEFAULT_REDUNDANCY: usize = 1;
+
+// Number of times a system reset is requested before giving up and never exiting
+const RESET_REQUEST_ATTEMPTS: usize = 3;
+
+// Seed and multiplier for the `FaultInjectionPrevention` configuration checksum
+const CONFIG_CHECKSUM_SEED: usize = const_random::const_random!(usize);
+const CONFIG_CHECKSUM_MULTIPLIER: usize = 0x9E37_79B9;
+
+// Default cycle range used by `secure_random_delay` and batched delays
+const SECURE_DELAY_MIN_CYCLES: u32 = 10;
+const SECURE_DELAY_MAX_CYCLES: u32 = 50;
+
+// Number of distinct values of a `u32` draw, used for rejection sampling in
+// `generate_secure_random`
+const RANDOM_DRAW_SPAN: u64 = 1 << 32;
+
+// Upper bound for the cycles of `adaptive_failure_delay`
+const ADAPTIVE_DELAY_CEILING_CYCLES: u32 = 1 << 24;
+
+// PRIMASK bit that masks all configurable-priority interrupts
+const PRIMASK_PM: u32 = 1;
+
+// Representation of `SecureBool`
+#[cfg(not(feature = "secure_bool_u64"))]
+type SecureBoolRepr = usize;
+#[cfg(feature = "secure_bool_u64")]
+type SecureBoolRepr = u64;
 
+#[cfg(not(feature = "secure_bool_u64"))]
 const CRITICAL_BOOL: usize = const_random::const_random!(usize);
-const CRITICAL_ERROR: usize = const_random::const_random!(usize);
+#[cfg(not(feature = "secure_bool_u64"))]
+const CRITICAL_ERROR_SEED: usize = const_random::const_random!(usize);
+
+#[cfg(feature = "secure_bool_u64")]
+const CRITICAL_BOOL: u64 = const_random::const_random!(u64);
+#[cfg(feature = "secure_bool_u64")]
+const CRITICAL_ERROR_SEED: u64 = const_random::const_random!(u64);
+
+// Low bit of every pair of bits
+const PAIR_LOW_BITS: SecureBoolRepr = SecureBoolRepr::MAX / 3;
+
+// Flips exactly one bit of every pair of bits of `CRITICAL_BOOL`, picked by the seed, so the error
+// value differs from both `SecureBool::True` and `SecureBool::False` in half of their bits.
+const CRITICAL_ERROR: SecureBoolRepr = CRITICAL_BOOL
+    ^ (CRITICAL_ERROR_SEED & PAIR_LOW_BITS)
+    ^ ((!CRITICAL_ERROR_SEED & PAIR_LOW_BITS) << 1);
+
+// A glitch must flip exactly half of the bits to turn `SecureBool::Error` into `SecureBool::True`
+// or `SecureBool::False`. The complement of the difference is the difference between
+// `CRITICAL_ERROR` and `!CRITICAL_BOOL`.
+const _: () = assert!((CRITICAL_BOOL ^ CRITICAL_ERROR).count_ones() == SecureBoolRepr::BITS / 2);
+const _: () = assert!((!(CRITICAL_BOOL ^ CRITICAL_ERROR)).count_ones() == SecureBoolRepr::BITS / 2);
 
 #[allow(missing_docs)]
+#[allow(clippy::enum_clike_unportable_variant)]
 #[derive(PartialEq, Eq, Clone, Copy, Default)]
-#[repr(usize)]
+#[cfg_attr(not(feature = "secure_bool_u64"), repr(usize))]
+#[cfg_attr(feature = "secure_bool_u64", repr(u64))]
 /// Large constants values for true and false. This makes it so attackers need
 /// to do more than flip a signle bit for a true/false flip.
+///
+/// By default, the constants are `usize` wide, which is 32 bits on ARMv7-M. Enable the
+/// `secure_bool_u64` feature to back the constants by a `u64`, which doubles the number of bits
+/// that need to be flipped to land on another valid value.
 pub enum SecureBool {
     True = CRITICAL_BOOL,
     False = !CRITICAL_BOOL,
@@ -131,6 +284,55 @@ impl From<bool> for SecureBool {
     }
 }
 
+/// A value that has been verified by one of the hardened primitives of this crate, e.g.
+/// [`FaultInjectionPrevention::critical_read()`]. A `Verified<T>` can't be constructed outside of
+/// this crate, so using a value that hasn't gone through a hardened path requires an explicit
+/// [`Verified::into_inner()`] or deref, which makes it visible in review.
+///
+/// ```compile_fail,E0423
+/// use fault_injection_protection_arm::Verified;
+///
+/// let unverified = Verified(1u32);
+/// ```
+///
+/// ```compile_fail,E0624
+/// use fault_injection_protection_arm::Verified;
+///
+/// let unverified = Verified::new(1u32);
+/// ```
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Verified<T>(T);
+
+impl<T> Verified<T> {
+    /// Wraps a value that has been verified by a hardened primitive.
+    #[inline(always)]
+    pub(crate) fn new(value: T) -> Self {
+        Verified(value)
+    }
+
+    /// Returns the verified value.
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Verified<T> {
+    type Target = T;
+
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> AsRef<T> for Verified<T> {
+    #[inline(always)]
+    fn as_ref(&self) -> &T {
+        &self.0
+    }
+}
+
 /// A phantom data type that is used by Rng closure traits
 pub struct RngNotUsed {}
 
@@ -188,6 +390,36 @@ where
     }
 }
 
+/// Register-width types that can be read with
+/// [`FaultInjectionPrevention::critical_read_fast()`] and
+/// [`FaultInjectionPrevention::critical_assign_if()`]. Sealed so these are only used for types
+/// that are read with a single load and can be masked.
+#[sealed]
+pub trait SmallCopy: Eq + Copy + Default {
+    /// Returns `a` if `choose_a` is true, and `b` otherwise, using masking instead of a branch.
+    #[doc(hidden)]
+    fn ct_select(a: Self, b: Self, choose_a: bool) -> Self;
+}
+
+macro_rules! impl_small_copy {
+    ($($t:ty),*) => {
+        $(
+            #[sealed]
+            impl SmallCopy for $t {
+                #[inline(always)]
+                fn ct_select(a: Self, b: Self, choose_a: bool) -> Self {
+                    // All ones if choose_a, all zeros otherwise.
+                    let mask = black_box(<$t>::from(choose_a).wrapping_neg());
+
+                    (a & mask) | (b & !mask)
+                }
+            }
+        )*
+    };
+}
+
+impl_small_copy!(u8, u16, u32, usize);
+
 /// Secure random delay errors
 ///
 /// # Errors
@@ -198,12 +430,61 @@ pub enum RandomError {
     InvalidRange,
 }
 
+/// A batch of pre-drawn random delay values for
+/// [`FaultInjectionPrevention::secure_random_delay_batched()`]. Drawing `N` delays at once amortizes
+/// the cost of the RNG when many delays occur in a burst. The batch starts empty and is refilled
+/// from the RNG whenever all of its delays have been consumed.
+///
+/// Tradeoff: pre-drawn delays sit in memory until they are consumed, so an attacker that can read
+/// memory learns the upcoming delays of the current batch. Each delay is cleared once it has been
+/// used, but this still reduces forward secrecy compared to
+/// [`FaultInjectionPrevention::secure_random_delay()`]. Keep `N` small, and prefer the unbatched
+/// delay where RNG cost is not a concern.
+pub struct DelayBatch<const N: usize> {
+    delays: [u32; N],
+    index: usize,
+}
+
+impl<const N: usize> DelayBatch<N> {
+    /// Creates a new empty delay batch. The batch is filled on first use.
+    pub const fn new() -> Self {
+        DelayBatch {
+            delays: [0u32; N],
+            index: N,
+        }
+    }
+}
+
+impl<const N: usize> Default for DelayBatch<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 /// A panic handler that never exits, even in cases of fault-injection attacks. Never inlined to
 /// allow breakpoints to be set.
+///
+/// With the `panic_resets` feature, the panic handler securely resets the device instead, so the
+/// device recovers without a power cycle. This trades the hang, which keeps the device in a known
+/// dead state, for a reboot that an attacker could retry repeatedly, so pair it with boot-time
+/// tamper logging or throttling.
+#[cfg(target_os = "none")]
 #[inline(never)]
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
-    never_exit!()
+    on_panic()
+}
+
+/// Handles a panic by securely resetting the device with the `panic_resets` feature, and by never
+/// exiting otherwise.
+#[cfg(any(target_os = "none", test))]
+#[inline(always)]
+fn on_panic() -> ! {
+    #[cfg(feature = "panic_resets")]
+    FaultInjectionPrevention::secure_reset_device();
+
+    #[cfg(not(feature = "panic_resets"))]
+    Arch::never_exit()
 }
 
 /// A macro for ensuring that code never exits, even in cases of fault-injection attacks.
@@ -240,13 +521,303 @@ macro_rules! never_exit {
     };
 }
 
+/// A macro for a loop that runs a body exactly `count` times, even in cases of fault-injection
+/// attacks. Expands to [`FaultInjectionPrevention::critical_for()`], which keeps the trip count
+/// hidden from the optimizer and verifies it after the loop. The body can optionally take the
+/// iteration index.
+///
+/// ```ignore
+/// critical_loop!(fip, &mut rng, 16, {
+///     mix_round();
+/// });
+///
+/// critical_loop!(fip, &mut rng, key.len(), |i| {
+///     key[i] ^= pad[i];
+/// });
+/// ```
+#[macro_export]
+macro_rules! critical_loop {
+    ($fip:expr, $rng:expr, $count:expr, |$index:ident| $body:block) => {
+        $fip.critical_for($count, |$index: usize| $body, $rng)
+    };
+    ($fip:expr, $rng:expr, $count:expr, $body:block) => {
+        $fip.critical_for($count, |_: usize| $body, $rng)
+    };
+}
+
+/// A macro for defining a critical function whose whole body runs inside
+/// [`FaultInjectionPrevention::stack_canary()`]. With `sp_guard`, the body runs inside
+/// [`FaultInjectionPrevention::stack_canary_with_sp_guard()`] instead, so the stack pointer is
+/// also verified. Generic functions aren't supported.
+///
+/// The first two arguments are the [`FaultInjectionPrevention`] and RNG expressions used for the
+/// canary. They are evaluated inside the function, so they can name its parameters, or refer to
+/// statics. If the RNG is a single identifier, such as a parameter, the body gets the RNG under
+/// the same name, so it can use it for other primitives. An RNG given as any other expression is
+/// borrowed by the canary for the whole call, so the body can't use it.
+///
+/// ```ignore
+/// critical_fn! {
+///     fip, rng;
+///     fn copy_input(fip: &FaultInjectionPrevention, rng: &mut Rng, input: &[u8]) -> usize {
+///         let mut buffer = [0u8; 16];
+///         let len = fip.critical_read(&input.len(), rng).into_inner();
+///         buffer[..len].copy_from_slice(input);
+///         len
+///     }
+/// }
+///
+/// critical_fn! {
+///     fip, rng, sp_guard;
+///     pub fn parse_header(fip: &FaultInjectionPrevention, rng: &mut Rng, header: &[u8]) {
+///         parse(header);
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! critical_fn {
+    (
+        $fip:expr, $rng:ident, sp_guard;
+        $(#[$meta:meta])*
+        $vis:vis fn $name:ident($($arg:ident: $arg_ty:ty),* $(,)?) $(-> $ret:ty)? $body:block
+    ) => {
+        $(#[$meta])*
+        $vis fn $name($($arg: $arg_ty),*) $(-> $ret)? {
+            $crate::critical_fn!(@rng_body stack_canary_with_sp_guard $fip, $rng, $body)
+        }
+    };
+    (
+        $fip:expr, $rng:ident;
+        $(#[$meta:meta])*
+        $vis:vis fn $name:ident($($arg:ident: $arg_ty:ty),* $(,)?) $(-> $ret:ty)? $body:block
+    ) => {
+        $(#[$meta])*
+        $vis fn $name($($arg: $arg_ty),*) $(-> $ret)? {
+            $crate::critical_fn!(@rng_body stack_canary $fip, $rng, $body)
+        }
+    };
+    (
+        $fip:expr, $rng:expr, sp_guard;
+        $(#[$meta:meta])*
+        $vis:vis fn $name:ident($($arg:ident: $arg_ty:ty),* $(,)?) $(-> $ret:ty)? $body:block
+    ) => {
+        $(#[$meta])*
+        $vis fn $name($($arg: $arg_ty),*) $(-> $ret)? {
+            $crate::critical_fn!(@body stack_canary_with_sp_guard $fip, $rng, $body)
+        }
+    };
+    (
+        $fip:expr, $rng:expr;
+        $(#[$meta:meta])*
+        $vis:vis fn $name:ident($($arg:ident: $arg_ty:ty),* $(,)?) $(-> $ret:ty)? $body:block
+    ) => {
+        $(#[$meta])*
+        $vis fn $name($($arg: $arg_ty),*) $(-> $ret)? {
+            $crate::critical_fn!(@body stack_canary $fip, $rng, $body)
+        }
+    };
+    (@rng_body $wrapper:ident $fip:expr, $rng:ident, $body:block) => {{
+        let mut result = ::core::option::Option::None;
+
+        // The RNG is passed to the body under the same name, since the canary borrows it for the
+        // whole call. The body runs in its own closure, so a `return` in it returns from the body.
+        #[allow(clippy::redundant_closure_call)]
+        $fip.$wrapper(
+            |$rng: &mut _| result = ::core::option::Option::Some((|| $body)()),
+            $rng,
+        );
+
+        $crate::critical_fn!(@result result)
+    }};
+    (@body $wrapper:ident $fip:expr, $rng:expr, $body:block) => {{
+        let mut result = ::core::option::Option::None;
+
+        // The body runs in its own closure, so a `return` in it returns from the body.
+        #[allow(clippy::redundant_closure_call)]
+        $fip.$wrapper(|| result = ::core::option::Option::Some((|| $body)()), $rng);
+
+        $crate::critical_fn!(@result result)
+    }};
+    (@result $result:ident) => {
+        match $result {
+            ::core::option::Option::Some(result) => result,
+            ::core::option::Option::None => $crate::FaultInjectionPrevention::secure_reset_device(),
+        }
+    };
+}
+
+/// An environment verification registered with
+/// [`FaultInjectionPrevention::with_preconditions()`]. Returns [`SecureBool::True`] if the
+/// environment is in the expected state.
+///
+/// ```ignore
+/// fn debug_locked(fip: &FaultInjectionPrevention, mut rng: &mut dyn CryptoRngCore) -> SecureBool {
+///     unsafe { fip.critical_verify_debug_locked(DHCSR_ADDR as *const u32, &mut rng) }
+/// }
+///
+/// static PRECONDITIONS: [Precondition; 1] = [debug_locked];
+///
+/// let fip = FaultInjectionPrevention::new().with_preconditions(&PRECONDITIONS);
+/// ```
+pub type Precondition = fn(&FaultInjectionPrevention, &mut dyn CryptoRngCore) -> SecureBool;
+
+/// Folds configuration fields into a checksum, for detecting corruption of a configuration.
+#[inline(always)]
+pub(crate) fn config_checksum(fields: &[usize]) -> usize {
+    fields
+        .iter()
+        .fold(CONFIG_CHECKSUM_SEED, |checksum, &field| {
+            (checksum ^ field)
+                .rotate_left(7)
+                .wrapping_mul(CONFIG_CHECKSUM_MULTIPLIER)
+        })
+}
+
 /// State for the fault-injection attack prevention library.
-pub struct FaultInjectionPrevention {}
+///
+/// The configuration is itself a glitch target that could weaken the protections, so a checksum of
+/// it is kept and verified at the start of each primitive.
+pub struct FaultInjectionPrevention {
+    redundancy: usize,
+    random_redundancy_min: usize,
+    random_redundancy_max: usize,
+    canary_rng: Option<fn() -> u64>,
+    preconditions: &'static [Precondition],
+    salt_max_ops: usize,
+    config_checksum: usize,
+}
 
 impl FaultInjectionPrevention {
     /// Initializes the state of the fault-injection attack prevention library.
     pub fn new() -> Self {
-        FaultInjectionPrevention {}
+        let mut fip = FaultInjectionPrevention {
+            redundancy: DEFAULT_REDUNDANCY,
+            random_redundancy_min: 0,
+            random_redundancy_max: 0,
+            canary_rng: None,
+            preconditions: &[],
+            salt_max_ops: 0,
+            config_checksum: 0,
+        };
+
+        fip.update_config_checksum();
+        fip
+    }
+
+    /// Sets the number of times redundant security checks are repeated, such as the final
+    /// comparison in [`FaultInjectionPrevention::stack_canary()`]. Higher values make it harder
+    /// for a single glitch to skip a check at the cost of speed. Values below 1 are treated as 1.
+    pub fn with_redundancy(mut self, redundancy: usize) -> Self {
+        self.redundancy = redundancy.max(1);
+        self.update_config_checksum();
+        self
+    }
+
+    /// Makes every [`FaultInjectionPrevention::critical_if()`] re-evaluate its condition a random
+    /// number of additional times within `[min, max]`, drawn from the RNG on each call. A fixed
+    /// number of checks gives attackers a predictable number of checks to skip, while a random
+    /// number can't be planned around. At least `min` additional passes always run. By default, no
+    /// additional passes are run.
+    ///
+    /// Returns an error if `min` is greater than `max`, or `max` doesn't fit in a `u32`.
+    pub fn with_random_redundancy(mut self, min: usize, max: usize) -> Result<Self, RandomError> {
+        if min > max || u32::try_from(max).is_err() {
+            return Err(RandomError::InvalidRange);
+        }
+
+        self.random_redundancy_min = min;
+        self.random_redundancy_max = max;
+        self.update_config_checksum();
+        Ok(self)
+    }
+
+    /// Sets a separate entropy source for the canaries of
+    /// [`FaultInjectionPrevention::stack_canary()`], e.g. a function that reads a hardware TRNG.
+    /// Canary generation and timing jitter then draw from independent sources, so recovering one
+    /// stream doesn't expose both defenses. By default, canaries are drawn from the same RNG as the
+    /// delays.
+    pub fn with_canary_rng(mut self, canary_rng: fn() -> u64) -> Self {
+        self.canary_rng = Some(canary_rng);
+        self.update_config_checksum();
+        self
+    }
+
+    /// Registers the environment verifications run by
+    /// [`FaultInjectionPrevention::critical_preconditions()`], e.g. closures that call
+    /// [`FaultInjectionPrevention::critical_verify_privilege()`] or
+    /// [`FaultInjectionPrevention::critical_verify_debug_locked()`].
+    pub fn with_preconditions(mut self, preconditions: &'static [Precondition]) -> Self {
+        self.preconditions = preconditions;
+        self.update_config_checksum();
+        self
+    }
+
+    /// Makes every [`FaultInjectionPrevention::critical_if()`] interleave its checks with up to
+    /// `max_dummy_ops` dummy [`black_box`] operations on throwaway values, with the number drawn
+    /// from the RNG at each point. The instruction pattern of the checks then varies per call, so
+    /// it can't be learned by profiling power traces. The result of the checks is unchanged. By
+    /// default, no dummy operations are run.
+    pub fn with_barrier_salting(mut self, max_dummy_ops: u32) -> Self {
+        self.salt_max_ops = max_dummy_ops as usize;
+        self.update_config_checksum();
+        self
+    }
+
+    /// Computes the checksum of the configuration. Must include every configuration field.
+    #[inline(always)]
+    fn compute_config_checksum(&self) -> usize {
+        // SAFETY: The fields are non-null, properly aligned, and initialized since they come from
+        // a Rust reference.
+        let fields = unsafe {
+            [
+                read_volatile(&self.redundancy),
+                read_volatile(&self.random_redundancy_min),
+                read_volatile(&self.random_redundancy_max),
+                read_volatile(&self.canary_rng).map_or(0, |canary_rng| canary_rng as usize),
+                read_volatile(&self.preconditions).as_ptr() as usize,
+                read_volatile(&self.preconditions).len(),
+                read_volatile(&self.salt_max_ops),
+            ]
+        };
+
+        config_checksum(&fields)
+    }
+
+    /// Recomputes the stored configuration checksum. Must be called after any change to the
+    /// configuration.
+    fn update_config_checksum(&mut self) {
+        self.config_checksum = self.compute_config_checksum();
+    }
+
+    /// Verifies that the configuration hasn't been corrupted since it was set. Doesn't use
+    /// [`FaultInjectionPrevention::critical_if()`], since `critical_if` itself calls this.
+    ///
+    /// If the configuration was corrupted, the board securely resets itself.
+    #[inline(always)]
+    pub(crate) fn verify_self(&self) {
+        Self::verify_checksum(&self.config_checksum, || self.compute_config_checksum());
+    }
+
+    /// Verifies that `stored` still matches the checksum returned by `compute`, for detecting
+    /// corruption of a configuration. Both operands are read again for a second comparison in the
+    /// opposite order, so skipping a single comparison isn't enough to accept a corrupted
+    /// configuration.
+    ///
+    /// If the checksums differ, the board securely resets itself.
+    #[inline(always)]
+    pub(crate) fn verify_checksum(stored: &usize, compute: impl Fn() -> usize) {
+        // SAFETY: stored is non-null, properly aligned, and initialized since it comes from a Rust
+        // reference.
+        if black_box(unsafe { read_volatile(stored) }) != black_box(compute()) {
+            Self::secure_reset_device();
+        }
+
+        helper::dsb();
+
+        // SAFETY: Same as above.
+        if black_box(compute()) != black_box(unsafe { read_volatile(stored) }) {
+            Self::secure_reset_device();
+        }
     }
 
     /// Ensures that if a function call is skipped, it never exits. Takes a function pointer with the
@@ -254,6 +825,7 @@ impl FaultInjectionPrevention {
     /// more than one instruction to exit the code. For maximum security, use [`never_exit`]!() if you
     /// are defining the inner most function that never exits. Avoid relying on this function if
     /// possible.
+    #[cfg(target_arch = "arm")]
     #[inline(always)]
     pub fn secure_never_exit_func(func: extern "aapcs" fn() -> !) -> ! {
         // SAFETY: func is a valid function pointer with the AAPCS calling convention.
@@ -272,23 +844,54 @@ impl FaultInjectionPrevention {
     /// Securely resets the device, ensuring that if an attacker skips the reset, they do not break
     /// into other code. Inlined to ensure that the attacker needs to skip more than one instruction
     /// to exit the code.
+    ///
+    /// The reset request is read back to confirm that it latched, and is retried a bounded number
+    /// of times if a glitched write didn't take effect. The device never exits either way.
+    ///
+    /// Before the reset is requested, every secret registered with
+    /// [`FaultInjectionPrevention::register_secret()`] is zeroized.
     #[inline(always)]
     pub fn secure_reset_device() -> ! {
+        zeroize::wipe_registered_secrets();
         helper::dsb();
 
-        // SAFETY: AIRCR_ADDR is a valid address for the AIRCR register, and is therefore properly
-        // aligned.
-        unsafe {
-            write_volatile(AIRCR_ADDR as *mut u32, AIRCR_VECTKEY | AIRCR_SYSRESETREQ);
+        for _ in 0..black_box(RESET_REQUEST_ATTEMPTS) {
+            Arch::request_reset();
+
+            helper::dsb();
+
+            if black_box(Arch::reset_requested()) {
+                break;
+            }
         }
 
-        helper::dsb();
+        Arch::never_exit()
+    }
 
-        never_exit!()
+    /// Same as [`FaultInjectionPrevention::secure_reset_device()`], but first records `addr` to the
+    /// retained fault address log when the `fault_address_log` feature is enabled. Only the address
+    /// of the memory that failed verification is recorded, never its value.
+    #[inline(always)]
+    pub(crate) fn secure_reset_device_at(addr: usize) -> ! {
+        #[cfg(feature = "fault_address_log")]
+        fault_log::record(addr);
+
+        #[cfg(not(feature = "fault_address_log"))]
+        let _ = addr;
+
+        Self::secure_reset_device()
     }
 
     /// Generates a secure random number within the specified range.
     ///
+    /// The number is drawn with rejection sampling, so it is uniform over `[min, max]` without
+    /// modulo bias, and the full `u32` range is supported. The rejection check and the reduction
+    /// into the range are computed redundantly after the draw is accepted. This doesn't use
+    /// [`FaultInjectionPrevention::critical_if()`], since `critical_if` itself calls this for its
+    /// delays.
+    ///
+    /// If the redundant computations disagree, the board securely resets itself.
+    ///
     /// # Arguments
     /// * `rng` - Cryptographically secure rng
     /// * `min` - The minimum value of the range.
@@ -304,9 +907,40 @@ impl FaultInjectionPrevention {
         if min > max {
             return Err(RandomError::InvalidRange);
         }
-        let range = max - min + 1;
-        let random_value = rng.next_u32() % range + min;
-        Ok(random_value)
+
+        // Computed in u64 so the full u32 range doesn't overflow. Draws at or above the largest
+        // multiple of the range that fits are rejected, so every value is equally likely.
+        let range = u64::from(max - min) + 1;
+        let limit = RANDOM_DRAW_SPAN - RANDOM_DRAW_SPAN % range;
+
+        let mut draw = u64::from(rng.next_u32());
+
+        while black_box(draw) >= limit {
+            draw = u64::from(rng.next_u32());
+        }
+
+        // Catch a glitch that skips the rejection above.
+        if black_box(draw) >= black_box(limit) {
+            Self::secure_reset_device();
+        }
+
+        let value = black_box(u64::from(min) + draw % range);
+        let value_shadow =
+            black_box(u64::from(black_box(min)) + black_box(draw) % black_box(range));
+
+        if black_box(value != value_shadow) || black_box(value > u64::from(max)) {
+            Self::secure_reset_device();
+        }
+
+        helper::dsb();
+
+        // Check the shadow copy as well, so skipping the check above doesn't let a glitched
+        // computation or an out of range value through.
+        if black_box(value_shadow != value) || black_box(value_shadow > u64::from(max)) {
+            Self::secure_reset_device();
+        }
+
+        Ok(value as u32)
     }
 
     /// A side-channel analysis resistant random delay function. Takes a range of possible cycles
@@ -328,7 +962,7 @@ impl FaultInjectionPrevention {
         max_cycles: u32,
     ) -> Result<(), RandomError> {
         let random_cycles = Self::generate_secure_random(rng, min_cycles, max_cycles)?;
-        delay(random_cycles);
+        Arch::delay(random_cycles);
         Ok(())
     }
 
@@ -337,7 +971,98 @@ impl FaultInjectionPrevention {
     /// timing. Inlined to eliminate branch to this function.
     #[inline(always)]
     pub fn secure_random_delay(&self, rng: &mut impl CryptoRngCore) {
-        self.secure_random_delay_cycles(rng, 10, 50).unwrap();
+        self.secure_random_delay_cycles(rng, SECURE_DELAY_MIN_CYCLES, SECURE_DELAY_MAX_CYCLES)
+            .unwrap();
+    }
+
+    /// A side-channel analysis resistant random delay function that consumes its delay from a
+    /// [`DelayBatch`] instead of drawing from the RNG on every call. Delays for the same 10-50
+    /// cycles as [`FaultInjectionPrevention::secure_random_delay()`]. When the batch is empty, it is
+    /// refilled with `N` new delays from the RNG. See [`DelayBatch`] for the forward-secrecy
+    /// tradeoff. Inlined to eliminate branch to this function.
+    #[inline(always)]
+    pub fn secure_random_delay_batched<const N: usize>(
+        &self,
+        batch: &mut DelayBatch<N>,
+        rng: &mut impl CryptoRngCore,
+    ) {
+        if N == 0 {
+            self.secure_random_delay(rng);
+            return;
+        }
+
+        if batch.index >= N {
+            for slot in batch.delays.iter_mut() {
+                *slot = Self::generate_secure_random(
+                    rng,
+                    SECURE_DELAY_MIN_CYCLES,
+                    SECURE_DELAY_MAX_CYCLES,
+                )
+                .unwrap();
+            }
+
+            batch.index = 0;
+        }
+
+        let random_cycles = batch.delays[batch.index];
+
+        // Clear the consumed delay so it can't be recovered from memory later.
+        // SAFETY: The slot is non-null and properly aligned since it comes from a Rust array.
+        unsafe {
+            write_volatile(&mut batch.delays[batch.index], 0);
+        }
+
+        batch.index += 1;
+        Arch::delay(random_cycles);
+    }
+
+    /// Returns the cycle range of [`FaultInjectionPrevention::adaptive_failure_delay()`] for
+    /// `failure_count` recent failures. The upper bound doubles with every failure until it
+    /// saturates at the ceiling, and the lower bound is half of the upper bound.
+    #[inline(always)]
+    fn adaptive_failure_delay_range(failure_count: u32) -> (u32, u32) {
+        let scale = 1u32 << failure_count.min(u32::BITS - 1);
+        let max_cycles = SECURE_DELAY_MAX_CYCLES
+            .saturating_mul(scale)
+            .min(ADAPTIVE_DELAY_CEILING_CYCLES);
+
+        (max_cycles / 2, max_cycles)
+    }
+
+    /// A random delay that grows with the number of recent failures, to throttle brute-force
+    /// attempts. The delay range starts at 25-50 cycles and doubles with every failure (exponential
+    /// backoff), saturating at 2^24 cycles, so repeated attacks get progressively slower. Call after
+    /// a failed authentication attempt with the number of recent failures.
+    #[inline(always)]
+    pub fn adaptive_failure_delay(&self, failure_count: u32, rng: &mut impl CryptoRngCore) {
+        let (min_cycles, max_cycles) = Self::adaptive_failure_delay_range(black_box(failure_count));
+
+        // The range is always valid since min_cycles is half of max_cycles.
+        self.secure_random_delay_cycles(rng, min_cycles, max_cycles)
+            .unwrap();
+    }
+
+    /// Runs a random number of dummy [`black_box`] operations on throwaway values, up to
+    /// `max_ops`, as set by [`FaultInjectionPrevention::with_barrier_salting()`] or
+    /// [`CriticalConfig::with_barrier_salting()`]. Does nothing if `max_ops` is 0. Returns the
+    /// number of dummy operations run.
+    #[inline(always)]
+    fn salt_barriers(max_ops: usize, rng: &mut impl CryptoRngCore) -> u32 {
+        if max_ops == 0 {
+            return 0;
+        }
+
+        // The range is always valid since max_ops was set from a u32.
+        let ops = Self::generate_secure_random(rng, 0, max_ops as u32).unwrap_or(0);
+        let mut throwaway: u32 = black_box(rng.next_u32());
+
+        for _ in 0..black_box(ops) {
+            throwaway =
+                black_box(black_box(throwaway).rotate_left(7) ^ CONFIG_CHECKSUM_MULTIPLIER as u32);
+        }
+
+        black_box(throwaway);
+        ops
     }
 
     /// To be used for a critical if statement that should be resistant to fault-injection attacks.
@@ -347,6 +1072,53 @@ impl FaultInjectionPrevention {
     #[allow(private_bounds)]
     pub fn critical_if<FnMutType, FnOnceType1, FnOnceType2, T: CryptoRngCore>(
         &self,
+        condition: impl RngFnMut<FnMutType, T>,
+        success: impl RngFnOnce<FnOnceType1, T>,
+        failure: impl RngFnOnce<FnOnceType2, T>,
+        rng: &mut T,
+    ) {
+        self.verify_self();
+
+        // SAFETY: The fields are non-null, properly aligned, and initialized since they come from
+        // a Rust reference.
+        let params = unsafe {
+            CheckParams {
+                min_passes: read_volatile(&self.random_redundancy_min),
+                max_passes: read_volatile(&self.random_redundancy_max),
+                delay_min_cycles: SECURE_DELAY_MIN_CYCLES,
+                delay_max_cycles: SECURE_DELAY_MAX_CYCLES,
+                salt_max_ops: read_volatile(&self.salt_max_ops),
+            }
+        };
+
+        Self::critical_if_with(params, condition, success, failure, rng);
+    }
+
+    /// Same as [`FaultInjectionPrevention::critical_if()`], but with the redundancy, delay, and
+    /// salting of `cfg` instead of the global configuration, so call sites with different threat
+    /// exposure can be hardened or relaxed individually. `cfg` is integrity checked before it is
+    /// used, so it can't be glitched to a weaker setting.
+    ///
+    /// If `cfg` or the global configuration was corrupted, the board securely resets itself.
+    #[allow(private_bounds)]
+    pub fn critical_if_cfg<FnMutType, FnOnceType1, FnOnceType2, T: CryptoRngCore>(
+        &self,
+        cfg: &CriticalConfig,
+        condition: impl RngFnMut<FnMutType, T>,
+        success: impl RngFnOnce<FnOnceType1, T>,
+        failure: impl RngFnOnce<FnOnceType2, T>,
+        rng: &mut T,
+    ) {
+        self.verify_self();
+        cfg.verify();
+
+        Self::critical_if_with(cfg.params(), condition, success, failure, rng);
+    }
+
+    /// Implementation of [`FaultInjectionPrevention::critical_if()`] with explicit parameters.
+    #[inline(always)]
+    fn critical_if_with<FnMutType, FnOnceType1, FnOnceType2, T: CryptoRngCore>(
+        params: CheckParams,
         mut condition: impl RngFnMut<FnMutType, T>,
         success: impl RngFnOnce<FnOnceType1, T>,
         failure: impl RngFnOnce<FnOnceType2, T>,
@@ -362,6 +1134,8 @@ impl FaultInjectionPrevention {
             write_volatile(&mut cond, SecureBool::Error);
         }
 
+        Self::salt_barriers(params.salt_max_ops, rng);
+
         if black_box(black_box(condition.exec(rng)) == SecureBool::False) {
             // SAFETY: cond is non-null and properly aligned since it comes from a
             // Rust variable. In addition SecureBool derives the Copy trait, so a
@@ -384,7 +1158,53 @@ impl FaultInjectionPrevention {
 
         helper::dsb();
 
-        self.secure_random_delay(rng);
+        // The range is always valid since it was validated when set.
+        Self::generate_secure_random(rng, params.delay_min_cycles, params.delay_max_cycles)
+            .map(Arch::delay)
+            .unwrap();
+
+        // Re-evaluate the condition a random number of times, so the number of checks can't be
+        // planned around. Both bounds fit in a u32 since they were validated when set.
+        let (min_passes, max_passes) = (params.min_passes, params.max_passes);
+        // A fixed number of passes doesn't need a draw from the RNG.
+        let extra_passes = if max_passes == 0 {
+            0
+        } else if min_passes == max_passes {
+            black_box(min_passes)
+        } else {
+            black_box(
+                Self::generate_secure_random(rng, min_passes as u32, max_passes as u32).unwrap_or(0)
+                    as usize,
+            )
+        };
+        let mut passes: usize = black_box(0);
+
+        for _ in 0..extra_passes {
+            Self::salt_barriers(params.salt_max_ops, rng);
+
+            // SAFETY: cond and passes are non-null, properly aligned, and initialized since they
+            // come from Rust variables.
+            unsafe {
+                if black_box(
+                    (black_box(condition.exec(rng)) == SecureBool::False)
+                        != (read_volatile(&cond) == SecureBool::False),
+                ) {
+                    Self::secure_reset_device();
+                }
+
+                write_volatile(&mut passes, read_volatile(&passes) + 1);
+            }
+        }
+
+        // SAFETY: passes is non-null, properly aligned, and initialized since it comes from a Rust
+        // variable.
+        if black_box(unsafe { read_volatile(&passes) } < min_passes)
+            || black_box(unsafe { read_volatile(&passes) } != extra_passes)
+        {
+            Self::secure_reset_device();
+        }
+
+        Self::salt_barriers(params.salt_max_ops, rng);
 
         if black_box(black_box(condition.exec(rng)) == SecureBool::False) {
             if black_box(black_box(condition.exec(rng)) == SecureBool::True) {
@@ -417,57 +1237,499 @@ impl FaultInjectionPrevention {
         helper::dsb();
     }
 
-    /// Stack canaries should be used anywhere where there is user input or
-    /// potential for user input, so overflow via glitching is difficult at
-    /// these points
-    /// ```
-    /// let mut user_input = [b'A'; 100];
-    /// let mut buffer: [u8; 16] = [0; 16];
-    /// fip.stack_canary(|| unsafe {
-    ///     copy(user_input.as_ptr(), buffer.as_mut_ptr(), user_input.len())
-    /// });
-    /// ```
+    /// Runs `condition` through [`FaultInjectionPrevention::critical_if()`] and returns the result
+    /// that the redundant checks agreed on. `condition` must only return [`SecureBool::True`] or
+    /// [`SecureBool::False`].
+    #[inline(always)]
+    pub(crate) fn critical_bool(
+        &self,
+        mut condition: impl FnMut() -> SecureBool,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        let result = Cell::new(SecureBool::Error);
 
-    #[inline(never)]
-    pub fn stack_canary(&self, run: impl FnOnce(), rng: &mut impl CryptoRngCore) {
-        // force canary to be allocated to stack instead of register
-        let mut canary: u64 = black_box(0);
+        self.critical_if(
+            &mut condition,
+            || result.set(SecureBool::True),
+            || result.set(SecureBool::False),
+            rng,
+        );
+
+        black_box(result.get())
+    }
+
+    /// Redundantly samples a value that can't be read through a Rust reference, such as a system
+    /// register read with inline asm. `read` is called twice and the samples are compared with
+    /// [`FaultInjectionPrevention::critical_if()`].
+    ///
+    /// If the samples disagree, the board securely resets itself.
+    #[inline(always)]
+    pub(crate) fn critical_sample<T>(
+        &self,
+        mut read: impl FnMut() -> T,
+        rng: &mut impl CryptoRngCore,
+    ) -> T
+    where
+        T: Eq + Copy,
+    {
+        let sample1 = black_box(read());
+        helper::dsb();
+        let sample2 = black_box(read());
+
+        self.critical_if(
+            || (black_box(sample1) == black_box(sample2)).into(),
+            || (),
+            || Self::secure_reset_device(),
+            rng,
+        );
+
+        black_box(sample1)
+    }
+
+    /// Polls `condition` until it holds or `max_polls` polls have run, then confirms the final
+    /// state through [`FaultInjectionPrevention::critical_if()`], so a glitch that exits the wait
+    /// early is caught.
+    ///
+    /// Returns [`SecureBool::True`] if `condition` holds, and [`SecureBool::False`] if it timed
+    /// out. If the redundant checks disagree, the board securely resets itself.
+    #[inline(always)]
+    pub(crate) fn critical_wait_for(
+        &self,
+        condition: impl Fn() -> bool,
+        max_polls: u32,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        for _ in 0..black_box(max_polls) {
+            if black_box(condition()) {
+                break;
+            }
+        }
+
+        helper::dsb();
+
+        self.critical_bool(|| black_box(condition()).into(), rng)
+    }
+
+    /// Runs `condition` through [`FaultInjectionPrevention::critical_if()`] and securely resets the
+    /// board unless the redundant checks agree that it holds.
+    #[inline(always)]
+    pub(crate) fn critical_assert(
+        &self,
+        mut condition: impl FnMut() -> bool,
+        rng: &mut impl CryptoRngCore,
+    ) {
+        self.critical_if(
+            || black_box(condition()).into(),
+            || (),
+            || Self::secure_reset_device(),
+            rng,
+        );
+    }
+
+    /// Compares two values in a way that is resistant to fault-injection attacks. Both operands are
+    /// forced through [`black_box`] and re-read with a volatile read on every redundant comparison,
+    /// so the compiler can't constant-fold the comparison and drop the redundancy even when one of
+    /// the operands is a compile-time constant, e.g. a fixed magic value.
+    ///
+    /// If a fault injection is detected, the board securely resets itself.
+    ///
+    /// ```ignore
+    /// const MAGIC: u32 = 0xDEAD_BEEF;
+    ///
+    /// if fip.critical_eq(header.magic, MAGIC, &mut rng) == SecureBool::True {
+    ///     // ...
+    /// }
+    /// ```
+    #[inline(always)]
+    pub fn critical_eq<T>(&self, a: T, b: T, rng: &mut impl CryptoRngCore) -> SecureBool
+    where
+        T: Eq + Copy,
+    {
+        let a = black_box(a);
+        let b = black_box(b);
+
+        // SAFETY: a and b are non-null, properly aligned, and initialized since they come from
+        // Rust variables.
+        self.critical_bool(
+            || unsafe { (read_volatile(black_box(&a)) == read_volatile(black_box(&b))).into() },
+            rng,
+        )
+    }
+
+    /// A barrier to place at security decision commit points. Performs a `dsb`, a
+    /// [`FaultInjectionPrevention::secure_random_delay()`], then re-evaluates `verify`, so a glitch
+    /// that flipped the decision before the commit point is caught.
+    ///
+    /// If `verify` no longer returns [`SecureBool::True`], the board securely resets itself.
+    ///
+    /// ```ignore
+    /// if fip.critical_eq(provided_pin, stored_pin, &mut rng) == SecureBool::True {
+    ///     fip.commit_barrier(|| (provided_pin == stored_pin).into(), &mut rng);
+    ///     unlock();
+    /// }
+    /// ```
+    #[inline(always)]
+    pub fn commit_barrier(&self, verify: impl Fn() -> SecureBool, rng: &mut impl CryptoRngCore) {
+        helper::dsb();
+
+        self.secure_random_delay(rng);
+
+        self.critical_assert(|| black_box(verify()) == SecureBool::True, rng);
+    }
+
+    /// Runs `body` exactly `count` times, passing the iteration index. The trip count is kept in
+    /// a [`black_box`]ed, volatile shadow, so the compiler can't unroll the loop and prove the
+    /// count, and the number of iterations is verified after the loop, so a glitched early exit or
+    /// skipped iteration is detected. See [`critical_loop`]!() for a macro form.
+    ///
+    /// If a fault injection is detected, the board securely resets itself.
+    #[inline(always)]
+    pub fn critical_for(
+        &self,
+        count: usize,
+        mut body: impl FnMut(usize),
+        rng: &mut impl CryptoRngCore,
+    ) {
+        self.verify_self();
+
+        let mut count_shadow: usize = black_box(0);
+        let mut index: usize = black_box(0);
+        let mut iterations: usize = black_box(0);
+
+        // SAFETY: The counters are non-null, properly aligned, and initialized since they come
+        // from Rust variables.
+        unsafe {
+            write_volatile(&mut count_shadow, black_box(count));
+
+            while Arch::read_source(&index) < read_volatile(&count_shadow) {
+                body(black_box(read_volatile(&index)));
+
+                write_volatile(&mut index, read_volatile(&index) + 1);
+                write_volatile(&mut iterations, read_volatile(&iterations) + 1);
+            }
+        }
+
+        helper::dsb();
+
+        // SAFETY: The counters are non-null, properly aligned, and initialized since they come
+        // from Rust variables.
+        self.critical_assert(
+            || unsafe {
+                read_volatile(&iterations) == black_box(count)
+                    && read_volatile(&index) == black_box(count)
+                    && read_volatile(&count_shadow) == black_box(count)
+            },
+            rng,
+        );
+    }
+
+    /// Runs `attempt` up to `N` times, stopping at the first attempt that returns
+    /// [`SecureBool::True`], for operations that are retried a fixed number of times, such as
+    /// programming flash. The attempts are tracked in two volatile counters, which are verified
+    /// after the loop to agree and not exceed `N`, so a glitch can't extend the retries.
+    ///
+    /// Returns [`SecureBool::True`] if an attempt succeeded, and [`SecureBool::False`] if all `N`
+    /// attempts failed. If a fault injection is detected, the board securely resets itself.
+    #[inline(always)]
+    pub fn critical_retry<const N: usize>(
+        &self,
+        mut attempt: impl FnMut() -> SecureBool,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        self.verify_self();
+
+        let mut attempts: usize = black_box(0);
+        let mut attempts_shadow: usize = black_box(0);
+        let mut succeeded = SecureBool::False;
+
+        // SAFETY: The counters and succeeded are non-null, properly aligned, and initialized since
+        // they come from Rust variables.
+        unsafe {
+            while Arch::read_source(&attempts) < black_box(N) {
+                let result = black_box(attempt());
+
+                write_volatile(&mut attempts, read_volatile(&attempts) + 1);
+                write_volatile(&mut attempts_shadow, read_volatile(&attempts_shadow) + 1);
+
+                if black_box(result) == SecureBool::True {
+                    write_volatile(&mut succeeded, SecureBool::True);
+                    break;
+                }
+            }
+        }
+
+        helper::dsb();
+
+        // A failed run must have used every attempt, so an early exit is caught too.
+        // SAFETY: The counters and succeeded are non-null, properly aligned, and initialized since
+        // they come from Rust variables.
+        self.critical_assert(
+            || unsafe {
+                read_volatile(&attempts) <= black_box(N)
+                    && read_volatile(&attempts_shadow) == read_volatile(&attempts)
+                    && (read_volatile(&succeeded) == SecureBool::True
+                        || read_volatile(&attempts) == black_box(N))
+            },
+            rng,
+        );
+
+        // SAFETY: succeeded is non-null, properly aligned, and initialized since it comes from a
+        // Rust variable.
+        self.critical_bool(
+            || unsafe { (read_volatile(&succeeded) == SecureBool::True).into() },
+            rng,
+        )
+    }
+
+    /// Runs `real` at a random position among equal-cost `decoys`, so an observer profiling
+    /// execution can't identify the real operation by its position. The decoys run in a random
+    /// rotation of their order. `real` is verified to have run exactly once and every decoy to
+    /// have run.
+    ///
+    /// If a fault injection is detected, the board securely resets itself.
+    #[inline(always)]
+    pub fn run_random_decoy(
+        &self,
+        real: impl FnOnce(),
+        decoys: &[&dyn Fn()],
+        rng: &mut impl CryptoRngCore,
+    ) {
+        self.verify_self();
+
+        let decoy_count = black_box(decoys.len());
+
+        // Both ranges are valid since the decoy count fits in a u32.
+        let real_position = Self::generate_secure_random(rng, 0, decoy_count as u32).unwrap();
+        let decoy_offset = if decoy_count == 0 {
+            0
+        } else {
+            Self::generate_secure_random(rng, 0, decoy_count as u32 - 1).unwrap() as usize
+        };
+
+        let mut real = Some(real);
+        let mut real_runs: usize = black_box(0);
+        let mut decoy_runs: usize = black_box(0);
+
+        for position in 0..=decoy_count {
+            // SAFETY: The counters are non-null, properly aligned, and initialized since they come
+            // from Rust variables.
+            unsafe {
+                if black_box(position) == real_position as usize {
+                    if let Some(real) = real.take() {
+                        real();
+                    }
+
+                    write_volatile(&mut real_runs, read_volatile(&real_runs) + 1);
+                } else {
+                    let decoy = read_volatile(&decoy_runs);
+                    decoys[(decoy + decoy_offset) % decoy_count]();
+
+                    write_volatile(&mut decoy_runs, decoy + 1);
+                }
+            }
+        }
+
+        helper::dsb();
+
+        // SAFETY: The counters are non-null, properly aligned, and initialized since they come
+        // from Rust variables.
+        self.critical_assert(
+            || unsafe {
+                read_volatile(&real_runs) == 1
+                    && read_volatile(&decoy_runs) == black_box(decoy_count)
+                    && real.is_none()
+            },
+            rng,
+        );
+    }
+
+    /// Stack canaries should be used anywhere where there is user input or
+    /// potential for user input, so overflow via glitching is difficult at
+    /// these points
+    ///
+    /// Canaries are drawn from the RNG set with
+    /// [`FaultInjectionPrevention::with_canary_rng()`] if there is one, and from `rng` otherwise.
+    ///
+    /// The final canary comparison is repeated according to
+    /// [`FaultInjectionPrevention::with_redundancy()`], with random delays in between.
+    ///
+    /// `run` can take the RNG as an argument, so it can use other primitives that need it.
+    ///
+    /// With the `canary_setup_verify` feature, the local canary is also verified
+    /// against the reference canary right before `run` is called, so corruption
+    /// during setup is caught before the critical function runs.
+    /// ```ignore
+    /// let mut user_input = [b'A'; 100];
+    /// let mut buffer: [u8; 16] = [0; 16];
+    /// fip.stack_canary(|| unsafe {
+    ///     copy(user_input.as_ptr(), buffer.as_mut_ptr(), user_input.len())
+    /// });
+    /// ```
+    #[inline(never)]
+    #[allow(private_bounds)]
+    pub fn stack_canary<FnOnceType, T: CryptoRngCore>(
+        &self,
+        run: impl RngFnOnce<FnOnceType, T>,
+        rng: &mut T,
+    ) {
+        self.verify_self();
+
+        // force canary to be allocated to stack instead of register
+        let mut canary: u64 = black_box(0);
 
         // SAFETY: No race conditions because this library only supports single
         // threaded programs
         unsafe {
-            // generate a new global canary at runtime using CryptoRngCore
-            // reference stored in fip struct
-            REF_CANARY.push(rng.next_u64(), self, rng);
+            // generate a new global canary at runtime using the canary RNG if one
+            // is configured, and the CryptoRngCore reference otherwise
+            let new_canary = match read_volatile(&self.canary_rng) {
+                Some(canary_rng) => canary_rng(),
+                None => rng.next_u64(),
+            };
+
+            (*ref_canary()).push(new_canary, self, rng);
 
             self.critical_write(
                 &mut canary,
-                REF_CANARY.peek(),
+                (*ref_canary()).peek(),
                 |dst, src| write_volatile(dst, src),
                 rng,
             );
         }
 
+        // Catch corruption of either canary between the push and running the critical function.
+        // SAFETY: No race conditions because this library only supports single
+        // threaded programs. canary is non-null, properly aligned, and initialized since it comes
+        // from a Rust variable.
+        #[cfg(feature = "canary_setup_verify")]
+        self.critical_assert(
+            || unsafe { read_volatile(&canary) == (*ref_canary()).peek() },
+            rng,
+        );
+
         helper::dsb();
-        run();
+        run.exec(rng);
 
         // SAFETY: No race conditions because this library only supports single
         // threaded programs
-        let reference_canary = unsafe { REF_CANARY.pop(self, rng) };
-        self.critical_if(
-            || (canary == reference_canary).into(),
-            || (),
-            || Self::secure_reset_device(),
+        let reference_canary = unsafe { (*ref_canary()).pop(self, rng) };
+
+        // Repeat the comparison so a single glitch can't bypass overflow detection.
+        let mut comparisons: usize = black_box(0);
+
+        for _ in 0..black_box(self.redundancy) {
+            self.critical_if(
+                || (canary == reference_canary).into(),
+                || (),
+                || Self::secure_reset_device(),
+                rng,
+            );
+
+            // SAFETY: comparisons is non-null, properly aligned, and initialized since it comes
+            // from a Rust variable.
+            unsafe {
+                write_volatile(&mut comparisons, read_volatile(&comparisons) + 1);
+            }
+
+            self.secure_random_delay(rng);
+        }
+
+        // Catch a glitch that exits the comparison loop early.
+        // SAFETY: comparisons is non-null, properly aligned, and initialized since it comes from
+        // a Rust variable.
+        self.critical_assert(
+            || unsafe { read_volatile(&comparisons) } == black_box(self.redundancy),
+            rng,
+        );
+    }
+
+    /// Runs `body` and verifies that the stack pointer is restored to its previous value
+    /// afterwards, catching stack-pivot or frame-corruption glitches during the call. SP is read
+    /// with inline asm before and after `body`, and the recorded value is kept in a volatile
+    /// local. Both reads are done by a function that is never inlined, so SP is stable between
+    /// them.
+    ///
+    /// If SP isn't restored, the board securely resets itself.
+    pub fn with_sp_guard(&self, body: impl FnOnce(), rng: &mut impl CryptoRngCore) {
+        self.sp_guarded(|_| body(), rng);
+    }
+
+    /// Same as [`FaultInjectionPrevention::stack_canary()`], but also verifies that the stack
+    /// pointer is restored after `run`, like [`FaultInjectionPrevention::with_sp_guard()`].
+    ///
+    /// If the canary was overwritten or SP isn't restored, the board securely resets itself.
+    #[allow(private_bounds)]
+    pub fn stack_canary_with_sp_guard<FnOnceType, T: CryptoRngCore>(
+        &self,
+        run: impl RngFnOnce<FnOnceType, T>,
+        rng: &mut T,
+    ) {
+        self.sp_guarded(
+            |rng| self.stack_canary(|rng: &mut T| run.exec(rng), rng),
+            rng,
+        );
+    }
+
+    /// Implementation of [`FaultInjectionPrevention::with_sp_guard()`] that passes `rng` through
+    /// to `body`, so `body` can run other primitives that need it.
+    #[inline(never)]
+    fn sp_guarded<T: CryptoRngCore>(&self, body: impl FnOnce(&mut T), rng: &mut T) {
+        let mut recorded_sp: usize = black_box(0);
+
+        // SAFETY: recorded_sp is non-null and properly aligned since it comes from a Rust
+        // variable.
+        unsafe {
+            write_volatile(&mut recorded_sp, self.critical_sample(Arch::read_sp, rng));
+        }
+
+        helper::dsb();
+        body(rng);
+        helper::dsb();
+
+        let current_sp = self.critical_sample(Arch::read_sp, rng);
+
+        // SAFETY: recorded_sp is non-null, properly aligned, and initialized since it comes from
+        // a Rust variable.
+        self.critical_assert(
+            || unsafe { read_volatile(&recorded_sp) } == black_box(current_sp),
             rng,
         );
     }
 
+    /// Runs `body` with interrupts disabled, so it can't be interrupted. After `cpsid`, PRIMASK is
+    /// read back and redundantly verified to mask interrupts, since a glitch on the disable could
+    /// leave them enabled and silently break the atomicity of `body`. Interrupts are re-enabled
+    /// afterwards only if they were enabled before the call, so critical sections can be nested.
+    ///
+    /// Returns the result of `body`. If interrupts weren't masked, the board securely resets
+    /// itself.
+    #[inline(always)]
+    pub fn critical_section<R>(&self, body: impl FnOnce() -> R, rng: &mut impl CryptoRngCore) -> R {
+        self.verify_self();
+
+        let was_masked = black_box(Arch::read_primask() & PRIMASK_PM != 0);
+
+        Arch::disable_interrupts();
+        helper::dsb();
+
+        self.critical_assert(|| black_box(Arch::read_primask()) & PRIMASK_PM != 0, rng);
+
+        let result = body();
+        helper::dsb();
+
+        if !black_box(was_masked) {
+            Arch::enable_interrupts();
+        }
+
+        result
+    }
+
     /// To be used for a critical memory reads that should be resistant to
     /// fault-injection attacks. If a fault injection is detected, the board
-    /// securely resets itself.
-
+    /// securely resets itself. The value that was read is returned as a
+    /// [`Verified`] value.
     #[inline(always)]
-    pub fn critical_read<T>(&self, src: &T, rng: &mut impl CryptoRngCore) -> T
+    pub fn critical_read<T>(&self, src: &T, rng: &mut impl CryptoRngCore) -> Verified<T>
     where
         T: Eq + Copy + Default,
     {
@@ -493,29 +1755,107 @@ impl FaultInjectionPrevention {
         // * dst is properly aligned
 
         unsafe {
-            write_volatile(black_box(&mut data1), read_volatile(black_box(src)));
+            write_volatile(black_box(&mut data1), Arch::read_source(black_box(src)));
         }
 
         unsafe {
-            write_volatile(black_box(&mut data2), read_volatile(black_box(src)));
+            write_volatile(black_box(&mut data2), Arch::read_source(black_box(src)));
         }
 
         unsafe {
-            write_volatile(black_box(&mut data1), read_volatile(black_box(src)));
+            write_volatile(black_box(&mut data1), Arch::read_source(black_box(src)));
         }
 
         unsafe {
-            write_volatile(black_box(&mut data2), read_volatile(black_box(src)));
+            write_volatile(black_box(&mut data2), Arch::read_source(black_box(src)));
         }
 
         self.critical_if(
             || (data1 == data2).into(),
             || (),
-            || Self::secure_reset_device(),
+            || Self::secure_reset_device_at(src as *const T as usize),
+            rng,
+        );
+
+        Verified::new(black_box(data1))
+    }
+
+    /// A faster [`FaultInjectionPrevention::critical_read()`] for register-width types, for use in
+    /// hot loops. The source is still read twice and the reads are compared twice, but the
+    /// comparison is done inline without the random delay and redundant condition evaluations of
+    /// [`FaultInjectionPrevention::critical_if()`]. Prefer
+    /// [`FaultInjectionPrevention::critical_read()`] outside of hot paths.
+    ///
+    /// If a fault injection is detected, the board securely resets itself.
+    #[inline(always)]
+    pub fn critical_read_fast<T: SmallCopy>(&self, src: &T) -> Verified<T> {
+        self.verify_self();
+
+        helper::dsb();
+
+        // SAFETY: src is valid for reads, properly initialized, and properly aligned because it
+        // is a Rust reference.
+        let data1 = black_box(unsafe { Arch::read_source(black_box(src)) });
+        let data2 = black_box(unsafe { Arch::read_source(black_box(src)) });
+
+        if black_box(data1 != data2) {
+            Self::secure_reset_device_at(src as *const T as usize);
+        }
+
+        helper::dsb();
+
+        // Repeat the comparison with the reads swapped, so a glitch that skips the check above
+        // still can't return a value that differs between the two reads.
+        if black_box(data2 != data1) {
+            Self::secure_reset_device_at(src as *const T as usize);
+        }
+
+        Verified::new(black_box(data1))
+    }
+
+    /// Writes `value` into `dst` if `cond` is [`SecureBool::True`], and leaves `dst` unchanged if it
+    /// is [`SecureBool::False`], without a data-dependent branch. The new value is selected with
+    /// masking and stored unconditionally with [`FaultInjectionPrevention::critical_write()`], so
+    /// the store happens at the hardware level either way. The final value of `dst` is then
+    /// verified against `cond`.
+    ///
+    /// If `cond` is [`SecureBool::Error`] or a fault injection is detected, the board securely
+    /// resets itself.
+    #[inline(always)]
+    pub fn critical_assign_if<T: SmallCopy>(
+        &self,
+        cond: SecureBool,
+        dst: &mut T,
+        value: T,
+        rng: &mut impl CryptoRngCore,
+    ) {
+        self.critical_assert(|| black_box(cond) != SecureBool::Error, rng);
+
+        let original = self.critical_read_fast(dst).into_inner();
+        let selected = T::ct_select(value, original, black_box(cond) == SecureBool::True);
+
+        self.critical_write(
+            dst,
+            selected,
+            // SAFETY: dst is non-null and properly aligned since it comes from a Rust reference.
+            |dst, src| unsafe { write_volatile(dst, src) },
             rng,
         );
 
-        black_box(data1)
+        // SAFETY: dst is valid for reads, properly initialized, and properly aligned because it
+        // is a Rust reference.
+        self.critical_assert(
+            || {
+                let current = unsafe { read_volatile(black_box(&*dst)) };
+
+                if black_box(cond) == SecureBool::True {
+                    current == value
+                } else {
+                    current == original
+                }
+            },
+            rng,
+        );
     }
 
     /// To be used for critical memory writes that need to be resilient to
@@ -524,7 +1864,30 @@ impl FaultInjectionPrevention {
     ///
     /// If a fault injection is detected, the board securely resets itself.
     ///
+    /// `T` must not be zero-sized, since the read-back of a zero-sized value is always equal and
+    /// the write provides no protection. Using a zero-sized `T` fails to compile.
+    ///
+    /// ```compile_fail,E0080
+    /// # use rand_core::{CryptoRng, Error, RngCore};
+    /// # struct Rng;
+    /// # impl RngCore for Rng {
+    /// #     fn next_u32(&mut self) -> u32 { 4 }
+    /// #     fn next_u64(&mut self) -> u64 { 4 }
+    /// #     fn fill_bytes(&mut self, dest: &mut [u8]) { dest.fill(4) }
+    /// #     fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+    /// #         Ok(dest.fill(4))
+    /// #     }
+    /// # }
+    /// # impl CryptoRng for Rng {}
+    /// use fault_injection_protection_arm::FaultInjectionPrevention;
+    ///
+    /// let fip = FaultInjectionPrevention::new();
+    /// let mut unit = ();
+    ///
+    /// fip.critical_write(&mut unit, (), |dst, src| *dst = src, &mut Rng);
     /// ```
+    ///
+    /// ```ignore
     /// let fip = FaultInjectionPrevention::new(|_| {});
     ///
     /// let mut buffer: [u8; 20] = [0; 20];
@@ -541,7 +1904,6 @@ impl FaultInjectionPrevention {
     ///    });
     /// }
     /// ```
-
     #[inline(always)]
     pub fn critical_write<T>(
         &self,
@@ -552,32 +1914,47 @@ impl FaultInjectionPrevention {
     ) where
         T: Eq + Copy + Default,
     {
+        const {
+            assert!(
+                size_of::<T>() != 0,
+                "critical_write can't verify writes of a zero-sized type"
+            );
+        }
+
         // All volatile memory reads/writes and ordering-sensitive operations
         // should use ARM dsb fence to guarantee no re-ordering in case volatile
         // is reordered due to detected no side effects
         helper::dsb();
 
+        let dst_addr = dst as *const T as usize;
+
         write_op(black_box(dst), black_box(src));
         self.critical_if(
-            || unsafe { (read_volatile(black_box(dst)) == read_volatile(black_box(&src))).into() },
+            || unsafe {
+                (Arch::read_source(black_box(dst)) == read_volatile(black_box(&src))).into()
+            },
             || (),
-            || Self::secure_reset_device(),
+            || Self::secure_reset_device_at(dst_addr),
             rng,
         );
 
         write_op(black_box(dst), black_box(src));
         self.critical_if(
-            || unsafe { (read_volatile(black_box(dst)) == read_volatile(black_box(&src))).into() },
+            || unsafe {
+                (Arch::read_source(black_box(dst)) == read_volatile(black_box(&src))).into()
+            },
             || (),
-            || Self::secure_reset_device(),
+            || Self::secure_reset_device_at(dst_addr),
             rng,
         );
 
         write_op(black_box(dst), black_box(src));
         self.critical_if(
-            || unsafe { (read_volatile(black_box(dst)) == read_volatile(black_box(&src))).into() },
+            || unsafe {
+                (Arch::read_source(black_box(dst)) == read_volatile(black_box(&src))).into()
+            },
             || (),
-            || Self::secure_reset_device(),
+            || Self::secure_reset_device_at(dst_addr),
             rng,
         );
     }
@@ -588,3 +1965,1253 @@ impl Default for FaultInjectionPrevention {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::mock;
+    use crate::test_util::{resets, TestRng};
+    use core::cell::{Cell, RefCell};
+    use rand_core::RngCore;
+
+    /// Returns which closure of a `critical_if` on `value` ran, and how many times the condition
+    /// was evaluated.
+    fn run_critical_if(fip: &FaultInjectionPrevention, value: SecureBool) -> (&str, usize) {
+        let branch = Cell::new("none");
+        let evaluations = Cell::new(0);
+
+        fip.critical_if(
+            || {
+                evaluations.set(evaluations.get() + 1);
+                value
+            },
+            || branch.set("success"),
+            || branch.set("failure"),
+            &mut TestRng(1),
+        );
+
+        (branch.get(), evaluations.get())
+    }
+
+    #[test]
+    fn critical_if_runs_matching_branch() {
+        let fip = FaultInjectionPrevention::new();
+
+        assert_eq!(run_critical_if(&fip, SecureBool::True), ("success", 4));
+        assert_eq!(run_critical_if(&fip, SecureBool::False), ("failure", 3));
+    }
+
+    #[test]
+    fn random_redundancy_varies_per_call_and_keeps_minimum() {
+        let fip = FaultInjectionPrevention::new()
+            .with_random_redundancy(2, 6)
+            .unwrap();
+        let mut rng = TestRng(1);
+        let mut seen = [false; 5];
+
+        for _ in 0..100 {
+            let evaluations = Cell::new(0);
+
+            fip.critical_if(
+                || {
+                    evaluations.set(evaluations.get() + 1);
+                    SecureBool::True
+                },
+                || (),
+                || (),
+                &mut rng,
+            );
+
+            // 4 evaluations without additional passes
+            seen[evaluations.get() - 4 - 2] = true;
+        }
+
+        assert_eq!(seen, [true; 5]);
+    }
+
+    #[test]
+    fn fixed_random_redundancy_runs_exact_passes() {
+        for passes in 1..=4 {
+            let fip = FaultInjectionPrevention::new()
+                .with_random_redundancy(passes, passes)
+                .unwrap();
+
+            assert_eq!(
+                run_critical_if(&fip, SecureBool::True),
+                ("success", 4 + passes)
+            );
+            assert_eq!(
+                run_critical_if(&fip, SecureBool::False),
+                ("failure", 3 + passes)
+            );
+        }
+    }
+
+    #[test]
+    fn fixed_random_redundancy_doesnt_draw_pass_count() {
+        let fip = FaultInjectionPrevention::new()
+            .with_random_redundancy(3, 3)
+            .unwrap();
+        let cfg = CriticalConfig::new().with_random_redundancy(3, 3).unwrap();
+
+        for seed in 0..50 {
+            // The RNG is only drawn from for the delay, like without additional passes.
+            let mut unfixed = TestRng(seed);
+            let mut fixed = TestRng(seed);
+            let mut fixed_cfg = TestRng(seed);
+
+            FaultInjectionPrevention::new().critical_if(
+                || SecureBool::True,
+                || (),
+                || (),
+                &mut unfixed,
+            );
+            fip.critical_if(|| SecureBool::True, || (), || (), &mut fixed);
+            fip.critical_if_cfg(&cfg, || SecureBool::True, || (), || (), &mut fixed_cfg);
+
+            assert_eq!(fixed.0, unfixed.0);
+            assert_eq!(fixed_cfg.0, unfixed.0);
+        }
+    }
+
+    #[test]
+    fn random_redundancy_rejects_invalid_range() {
+        assert!(FaultInjectionPrevention::new()
+            .with_random_redundancy(3, 2)
+            .is_err());
+    }
+
+    #[test]
+    fn barrier_salting_keeps_critical_if_result() {
+        let fip = FaultInjectionPrevention::new().with_barrier_salting(8);
+        let cfg = CriticalConfig::new().with_barrier_salting(8);
+
+        for seed in 0..200 {
+            for (value, expected) in [
+                (SecureBool::True, "success"),
+                (SecureBool::False, "failure"),
+            ] {
+                let branch = Cell::new("none");
+
+                fip.critical_if(
+                    || value,
+                    || branch.set("success"),
+                    || branch.set("failure"),
+                    &mut TestRng(seed),
+                );
+                assert_eq!(branch.get(), expected);
+
+                branch.set("none");
+                fip.critical_if_cfg(
+                    &cfg,
+                    || value,
+                    || branch.set("success"),
+                    || branch.set("failure"),
+                    &mut TestRng(seed),
+                );
+                assert_eq!(branch.get(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn barrier_salting_emits_varying_dummy_ops() {
+        let mut rng = TestRng(1);
+        let mut seen = [false; 9];
+
+        for _ in 0..200 {
+            let ops = FaultInjectionPrevention::salt_barriers(8, &mut rng);
+
+            seen[ops as usize] = true;
+        }
+
+        assert_eq!(seen, [true; 9]);
+    }
+
+    #[test]
+    fn barrier_salting_is_off_by_default() {
+        let mut rng = TestRng(1);
+
+        assert_eq!(FaultInjectionPrevention::salt_barriers(0, &mut rng), 0);
+        assert_eq!(rng.0, 1);
+
+        // Without salting, critical_if only draws from the RNG for its delays.
+        let mut unsalted = TestRng(1);
+        let mut salted = TestRng(1);
+
+        FaultInjectionPrevention::new().critical_if(
+            || SecureBool::True,
+            || (),
+            || (),
+            &mut unsalted,
+        );
+        FaultInjectionPrevention::new()
+            .with_barrier_salting(8)
+            .critical_if(|| SecureBool::True, || (), || (), &mut salted);
+        assert_ne!(unsalted.0, salted.0);
+    }
+
+    #[test]
+    fn critical_section_masks_interrupts_during_body() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        let primask = fip.critical_section(Arch::read_primask, &mut rng);
+
+        assert_eq!(primask & PRIMASK_PM, PRIMASK_PM);
+        assert_eq!(Arch::read_primask() & PRIMASK_PM, 0);
+    }
+
+    #[test]
+    fn critical_section_keeps_interrupts_masked_when_nested() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        fip.critical_section(
+            || {
+                fip.critical_section(|| (), &mut TestRng(2));
+                assert_eq!(Arch::read_primask() & PRIMASK_PM, PRIMASK_PM);
+            },
+            &mut rng,
+        );
+
+        assert_eq!(Arch::read_primask() & PRIMASK_PM, 0);
+    }
+
+    #[test]
+    fn critical_section_resets_when_disable_has_no_effect() {
+        let fip = FaultInjectionPrevention::new();
+        let ran = Cell::new(false);
+
+        mock::ignore_interrupt_disables();
+
+        assert!(resets(|| {
+            fip.critical_section(|| ran.set(true), &mut TestRng(1));
+        }));
+        assert!(!ran.get());
+    }
+
+    #[test]
+    fn critical_if_passes_rng_to_closures() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+        let drawn = Cell::new(0);
+
+        fip.critical_if(
+            |rng: &mut TestRng| {
+                rng.next_u32();
+                SecureBool::True
+            },
+            |rng: &mut TestRng| drawn.set(rng.next_u32()),
+            || (),
+            &mut rng,
+        );
+
+        assert_ne!(drawn.get(), 0);
+    }
+
+    #[test]
+    fn critical_if_resets_when_condition_flips() {
+        let fip = FaultInjectionPrevention::new();
+
+        // Flip the condition after every evaluation but the last in turn, in both directions.
+        for (initial, flipped, evaluated) in [
+            (SecureBool::True, SecureBool::False, 4),
+            (SecureBool::False, SecureBool::True, 3),
+        ] {
+            for flip_at in 1..evaluated {
+                let evaluations = Cell::new(0);
+
+                assert!(resets(|| fip.critical_if(
+                    || {
+                        evaluations.set(evaluations.get() + 1);
+
+                        if evaluations.get() > flip_at {
+                            flipped
+                        } else {
+                            initial
+                        }
+                    },
+                    || (),
+                    || (),
+                    &mut TestRng(1),
+                )));
+            }
+        }
+    }
+
+    #[test]
+    fn secure_bool_values_differ_in_many_bits() {
+        let values = [SecureBool::True, SecureBool::False, SecureBool::Error];
+
+        for (i, &a) in values.iter().enumerate() {
+            for &b in &values[i + 1..] {
+                let distance = (a as SecureBoolRepr ^ b as SecureBoolRepr).count_ones();
+                assert!(distance >= SecureBoolRepr::BITS / 2, "{distance}");
+            }
+        }
+    }
+
+    #[cfg(feature = "secure_bool_u64")]
+    #[test]
+    fn secure_bool_is_64_bits_wide() {
+        assert_eq!(core::mem::size_of::<SecureBool>(), 8);
+        assert_eq!(
+            (SecureBool::True as u64 ^ SecureBool::Error as u64).count_ones(),
+            32
+        );
+        assert_eq!(
+            (SecureBool::False as u64 ^ SecureBool::Error as u64).count_ones(),
+            32
+        );
+    }
+
+    /// RNG that returns `draws` in order, to drive rejection sampling with chosen values.
+    struct SequenceRng<'a> {
+        draws: &'a [u32],
+    }
+
+    impl RngCore for SequenceRng<'_> {
+        fn next_u32(&mut self) -> u32 {
+            let (&draw, rest) = self.draws.split_first().unwrap();
+            self.draws = rest;
+            draw
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            (u64::from(self.next_u32()) << 32) | u64::from(self.next_u32())
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            for byte in dest {
+                *byte = self.next_u32() as u8;
+            }
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl rand_core::CryptoRng for SequenceRng<'_> {}
+
+    /// Draws `samples` values within `[min, max]` and returns how often each value was drawn,
+    /// grouped into `buckets` equally sized buckets.
+    fn random_histogram(min: u32, max: u32, buckets: usize, samples: usize) -> Vec<usize> {
+        let mut rng = TestRng(1);
+        let mut histogram = vec![0; buckets];
+        let range = u64::from(max - min) + 1;
+
+        for _ in 0..samples {
+            let value =
+                FaultInjectionPrevention::generate_secure_random(&mut rng, min, max).unwrap();
+
+            assert!((min..=max).contains(&value));
+            histogram[(u64::from(value - min) * buckets as u64 / range) as usize] += 1;
+        }
+
+        histogram
+    }
+
+    /// Asserts that every bucket of `histogram` is within 15% of an even split.
+    fn assert_uniform(histogram: &[usize]) {
+        let expected = histogram.iter().sum::<usize>() / histogram.len();
+
+        for &count in histogram {
+            assert!(
+                count.abs_diff(expected) < expected * 15 / 100,
+                "{histogram:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn generate_secure_random_covers_full_range() {
+        // Every draw is accepted, without overflowing the range.
+        let mut rng = SequenceRng {
+            draws: &[u32::MAX, 0, 0x1234_5678],
+        };
+
+        for draw in [u32::MAX, 0, 0x1234_5678] {
+            assert_eq!(
+                FaultInjectionPrevention::generate_secure_random(&mut rng, 0, u32::MAX).unwrap(),
+                draw
+            );
+        }
+
+        assert_uniform(&random_histogram(0, u32::MAX, 16, 16_000));
+    }
+
+    #[test]
+    fn generate_secure_random_is_uniform_over_power_of_two_range() {
+        // 2^32 is a multiple of the range, so nothing is rejected.
+        let mut rng = SequenceRng { draws: &[u32::MAX] };
+        assert_eq!(
+            FaultInjectionPrevention::generate_secure_random(&mut rng, 16, 31).unwrap(),
+            31
+        );
+
+        assert_uniform(&random_histogram(16, 31, 16, 16_000));
+    }
+
+    #[test]
+    fn generate_secure_random_rejects_biased_tail_of_prime_range() {
+        // 2^32 % 7 == 4, so the top 4 draws would make the low values more likely and are
+        // rejected.
+        let mut rng = SequenceRng {
+            draws: &[u32::MAX, u32::MAX - 3, u32::MAX - 4],
+        };
+        assert_eq!(
+            FaultInjectionPrevention::generate_secure_random(&mut rng, 3, 9).unwrap(),
+            3 + ((u32::MAX - 4) % 7)
+        );
+        assert!(rng.draws.is_empty());
+
+        assert_uniform(&random_histogram(3, 9, 7, 14_000));
+    }
+
+    #[test]
+    fn generate_secure_random_handles_single_value_and_invalid_ranges() {
+        let mut rng = TestRng(1);
+
+        assert_eq!(
+            FaultInjectionPrevention::generate_secure_random(&mut rng, 5, 5).unwrap(),
+            5
+        );
+        assert!(matches!(
+            FaultInjectionPrevention::generate_secure_random(&mut rng, 6, 5),
+            Err(RandomError::InvalidRange)
+        ));
+    }
+
+    #[test]
+    fn secure_random_delay_batched_consumes_and_refills_batch() {
+        let fip = FaultInjectionPrevention::new();
+        let mut batch = DelayBatch::<4>::new();
+        let mut rng = TestRng(1);
+
+        fip.secure_random_delay_batched(&mut batch, &mut rng);
+
+        // The batch was filled, and the first delay was consumed and cleared.
+        let after_refill = rng.0;
+        assert_eq!(batch.index, 1);
+        assert_eq!(batch.delays[0], 0);
+        assert!(batch.delays[1..]
+            .iter()
+            .all(|delay| (SECURE_DELAY_MIN_CYCLES..=SECURE_DELAY_MAX_CYCLES).contains(delay)));
+
+        // The remaining delays are consumed without drawing from the RNG.
+        for index in 2..=4 {
+            fip.secure_random_delay_batched(&mut batch, &mut rng);
+            assert_eq!(batch.index, index);
+            assert_eq!(batch.delays[index - 1], 0);
+        }
+
+        assert_eq!(rng.0, after_refill);
+        assert_eq!(batch.delays, [0; 4]);
+
+        // An empty batch is refilled.
+        fip.secure_random_delay_batched(&mut batch, &mut rng);
+        assert_ne!(rng.0, after_refill);
+        assert_eq!(batch.index, 1);
+        assert!(batch.delays[1..].iter().all(|&delay| delay != 0));
+    }
+
+    #[test]
+    fn secure_random_delay_batched_draws_every_delay_without_batch() {
+        let fip = FaultInjectionPrevention::new();
+        let mut batch = DelayBatch::<0>::new();
+        let mut rng = TestRng(1);
+
+        fip.secure_random_delay_batched(&mut batch, &mut rng);
+        let after_first = rng.0;
+        fip.secure_random_delay_batched(&mut batch, &mut rng);
+
+        assert_ne!(rng.0, after_first);
+    }
+
+    std::thread_local! {
+        static COMPARISONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// A value whose comparisons are counted, so a folded comparison is visible.
+    #[derive(Clone, Copy, Eq)]
+    struct Counted(u32);
+
+    impl PartialEq for Counted {
+        fn eq(&self, other: &Self) -> bool {
+            COMPARISONS.set(COMPARISONS.get() + 1);
+            self.0 == other.0
+        }
+    }
+
+    /// Run with `--profile opt-z` to check that the comparisons aren't folded at `opt-level = "z"`
+    /// with LTO.
+    #[test]
+    fn critical_eq_isnt_folded_with_constant_operand() {
+        const MAGIC: Counted = Counted(0xDEAD_BEEF);
+
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        assert!(fip.critical_eq(Counted(0xDEAD_BEEF), MAGIC, &mut rng) == SecureBool::True);
+        assert_eq!(COMPARISONS.replace(0), 4);
+
+        assert!(fip.critical_eq(Counted(0), MAGIC, &mut rng) == SecureBool::False);
+        assert_eq!(COMPARISONS.replace(0), 3);
+    }
+
+    #[test]
+    fn adaptive_failure_delay_range_grows_with_failures() {
+        let mut previous = FaultInjectionPrevention::adaptive_failure_delay_range(0);
+        assert_eq!(
+            previous,
+            (SECURE_DELAY_MAX_CYCLES / 2, SECURE_DELAY_MAX_CYCLES)
+        );
+
+        for failure_count in 1..=18 {
+            let range = FaultInjectionPrevention::adaptive_failure_delay_range(failure_count);
+
+            assert_eq!(range, (previous.0 * 2, previous.1 * 2));
+            previous = range;
+        }
+    }
+
+    #[test]
+    fn adaptive_failure_delay_range_saturates_at_ceiling() {
+        let saturated = (
+            ADAPTIVE_DELAY_CEILING_CYCLES / 2,
+            ADAPTIVE_DELAY_CEILING_CYCLES,
+        );
+        assert!(
+            FaultInjectionPrevention::adaptive_failure_delay_range(18).1
+                < ADAPTIVE_DELAY_CEILING_CYCLES
+        );
+
+        for failure_count in [19, 31, 32, 1000, u32::MAX] {
+            assert_eq!(
+                FaultInjectionPrevention::adaptive_failure_delay_range(failure_count),
+                saturated
+            );
+        }
+    }
+
+    #[test]
+    fn adaptive_failure_delay_delays_once_at_any_failure_count() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        for (failure_count, delays) in [(0, 1), (20, 2), (u32::MAX, 3)] {
+            fip.adaptive_failure_delay(failure_count, &mut rng);
+            assert_eq!(mock::delays(), delays);
+        }
+    }
+
+    #[test]
+    fn commit_barrier_passes_when_verification_holds() {
+        let fip = FaultInjectionPrevention::new();
+        let pin = 1234;
+
+        fip.commit_barrier(|| (black_box(pin) == 1234).into(), &mut TestRng(1));
+    }
+
+    #[test]
+    fn commit_barrier_resets_when_verification_flips() {
+        let fip = FaultInjectionPrevention::new();
+
+        // The decision was flipped before the commit point.
+        assert!(resets(
+            || fip.commit_barrier(|| SecureBool::False, &mut TestRng(1))
+        ));
+
+        // The decision flips while the barrier re-verifies it.
+        let evaluations = Cell::new(0);
+        assert!(resets(|| fip.commit_barrier(
+            || {
+                evaluations.set(evaluations.get() + 1);
+                (evaluations.get() == 1).into()
+            },
+            &mut TestRng(1),
+        )));
+    }
+
+    #[test]
+    fn critical_read_returns_source() {
+        let fip = FaultInjectionPrevention::new();
+        let value: u32 = 0x1234_5678;
+
+        assert_eq!(
+            fip.critical_read(&value, &mut TestRng(1)).into_inner(),
+            value
+        );
+    }
+
+    #[test]
+    fn critical_read_resets_when_source_changes_between_reads() {
+        let fip = FaultInjectionPrevention::new();
+        let value: u32 = 0x1234_5678;
+
+        // The source is read 4 times and the last 2 reads are compared, like the 2 reads of
+        // critical_read_fast(). A change before them is a legitimate update of the source.
+        for reads in 0..3 {
+            mock::change_source_after(reads);
+            assert_eq!(
+                fip.critical_read(&value, &mut TestRng(1)).into_inner(),
+                !value
+            );
+        }
+
+        mock::change_source_after(3);
+        assert!(resets(|| {
+            fip.critical_read(&value, &mut TestRng(1));
+        }));
+    }
+
+    #[test]
+    fn critical_read_fast_resets_when_source_changes_between_reads() {
+        let fip = FaultInjectionPrevention::new();
+        let value: u32 = 0x1234_5678;
+
+        mock::change_source_after(0);
+        assert_eq!(fip.critical_read_fast(&value).into_inner(), !value);
+
+        mock::change_source_after(1);
+        assert!(resets(|| {
+            fip.critical_read_fast(&value);
+        }));
+    }
+
+    #[test]
+    fn verified_values_give_access_to_the_read_value() {
+        let fip = FaultInjectionPrevention::new();
+        let value: u32 = 0x1234_5678;
+        let verified = fip.critical_read(&value, &mut TestRng(1));
+        let verified_fast = fip.critical_read_fast(&value);
+
+        assert_eq!(*verified, value);
+        assert_eq!(*verified.as_ref(), value);
+        assert_eq!(verified, verified_fast);
+        assert_eq!(verified_fast.into_inner(), value);
+    }
+
+    #[test]
+    fn ct_select_selects_by_mask() {
+        assert_eq!(u8::ct_select(0xA5, 0x5A, true), 0xA5);
+        assert_eq!(u8::ct_select(0xA5, 0x5A, false), 0x5A);
+        assert_eq!(u16::ct_select(0xFFFF, 1, true), 0xFFFF);
+        assert_eq!(u32::ct_select(0, u32::MAX, false), u32::MAX);
+        assert_eq!(usize::ct_select(usize::MAX, 0, true), usize::MAX);
+    }
+
+    #[test]
+    fn critical_assign_if_assigns_only_when_true() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        let mut dst: u32 = 0x1111_1111;
+        fip.critical_assign_if(SecureBool::True, &mut dst, 0x2222_2222, &mut rng);
+        assert_eq!(dst, 0x2222_2222);
+
+        let mut dst: u32 = 0x1111_1111;
+        fip.critical_assign_if(SecureBool::False, &mut dst, 0x2222_2222, &mut rng);
+        assert_eq!(dst, 0x1111_1111);
+
+        let mut dst: u8 = 1;
+        fip.critical_assign_if(SecureBool::True, &mut dst, 2, &mut rng);
+        assert_eq!(dst, 2);
+    }
+
+    #[test]
+    fn critical_assign_if_runs_same_operations_for_both_conditions() {
+        let fip = FaultInjectionPrevention::new();
+        let mut dst: u32 = 0x1111_1111;
+
+        fip.critical_assign_if(SecureBool::True, &mut dst, 0x2222_2222, &mut TestRng(1));
+        let delays_true = mock::delays();
+
+        fip.critical_assign_if(SecureBool::False, &mut dst, 0x3333_3333, &mut TestRng(1));
+        assert_eq!(mock::delays() - delays_true, delays_true);
+    }
+
+    #[test]
+    fn critical_assign_if_resets_on_error() {
+        let fip = FaultInjectionPrevention::new();
+        let mut dst: u32 = 0x1111_1111;
+
+        assert!(resets(|| {
+            fip.critical_assign_if(SecureBool::Error, &mut dst, 0x2222_2222, &mut TestRng(1));
+        }));
+        assert_eq!(dst, 0x1111_1111);
+    }
+
+    #[test]
+    fn critical_for_runs_body_count_times() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        for count in [0, 1, 5] {
+            let mut indices = Vec::new();
+
+            fip.critical_for(count, |index| indices.push(index), &mut rng);
+            assert_eq!(indices, (0..count).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn critical_loop_runs_body_count_times() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+        let mut runs = 0;
+        let mut indices = Vec::new();
+
+        crate::critical_loop!(fip, &mut rng, 3, {
+            runs += 1;
+        });
+        crate::critical_loop!(fip, &mut rng, 4, |index| {
+            indices.push(index);
+        });
+
+        assert_eq!(runs, 3);
+        assert_eq!(indices, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn critical_for_resets_on_early_exit() {
+        let fip = FaultInjectionPrevention::new();
+        let mut runs = 0;
+
+        // The third loop condition reads a corrupted index, which exits the loop early.
+        mock::change_source_after(2);
+        assert!(resets(|| fip.critical_for(
+            4,
+            |_| runs += 1,
+            &mut TestRng(1)
+        )));
+        assert_eq!(runs, 2);
+    }
+
+    /// Runs `critical_retry::<3>()` with attempts that succeed from attempt `first_success` on,
+    /// and returns the result and the number of attempts.
+    fn retry_until(first_success: usize) -> (SecureBool, usize) {
+        let fip = FaultInjectionPrevention::new();
+        let mut attempts = 0;
+
+        let result = fip.critical_retry::<3>(
+            || {
+                attempts += 1;
+                (attempts >= first_success).into()
+            },
+            &mut TestRng(1),
+        );
+
+        (result, attempts)
+    }
+
+    #[test]
+    fn critical_retry_stops_at_first_success() {
+        assert!(retry_until(1) == (SecureBool::True, 1));
+        assert!(retry_until(2) == (SecureBool::True, 2));
+        assert!(retry_until(3) == (SecureBool::True, 3));
+    }
+
+    #[test]
+    fn critical_retry_fails_after_every_attempt_fails() {
+        assert!(retry_until(usize::MAX) == (SecureBool::False, 3));
+    }
+
+    #[test]
+    fn critical_retry_resets_on_early_exit() {
+        let fip = FaultInjectionPrevention::new();
+        let mut attempts = 0;
+
+        // The second loop condition reads a corrupted counter, which exits the loop early.
+        mock::change_source_after(1);
+        assert!(resets(|| {
+            fip.critical_retry::<3>(
+                || {
+                    attempts += 1;
+                    SecureBool::False
+                },
+                &mut TestRng(1),
+            );
+        }));
+        assert_eq!(attempts, 1);
+    }
+
+    /// Runs `run_random_decoy()` with `decoy_count` decoys and returns the order in which the
+    /// operations ran, with the real operation as `usize::MAX`.
+    fn random_decoy_order(decoy_count: usize, rng: &mut TestRng) -> Vec<usize> {
+        let fip = FaultInjectionPrevention::new();
+        let order = RefCell::new(Vec::new());
+        let order_ref = &order;
+        let decoys: Vec<_> = (0..decoy_count)
+            .map(|decoy| move || order_ref.borrow_mut().push(decoy))
+            .collect();
+        let decoys: Vec<&dyn Fn()> = decoys.iter().map(|decoy| decoy as &dyn Fn()).collect();
+
+        fip.run_random_decoy(|| order.borrow_mut().push(usize::MAX), &decoys, rng);
+        order.into_inner()
+    }
+
+    #[test]
+    fn run_random_decoy_runs_real_once_and_every_decoy() {
+        let mut rng = TestRng(1);
+
+        for decoy_count in 0..=4 {
+            let mut order = random_decoy_order(decoy_count, &mut rng);
+
+            assert_eq!(order.iter().filter(|&&op| op == usize::MAX).count(), 1);
+            order.sort_unstable();
+            order.pop();
+            assert_eq!(order, (0..decoy_count).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn run_random_decoy_places_real_at_every_position() {
+        let mut rng = TestRng(1);
+        let mut seen = [false; 4];
+
+        for _ in 0..64 {
+            let order = random_decoy_order(3, &mut rng);
+            seen[order.iter().position(|&op| op == usize::MAX).unwrap()] = true;
+        }
+
+        assert_eq!(seen, [true; 4]);
+    }
+
+    #[test]
+    fn critical_write_writes_value() {
+        let fip = FaultInjectionPrevention::new();
+        let mut dst: u32 = 0;
+        let mut writes = 0;
+
+        fip.critical_write(
+            &mut dst,
+            0xA5A5_A5A5,
+            |dst, src| {
+                writes += 1;
+                *dst = src;
+            },
+            &mut TestRng(1),
+        );
+
+        assert_eq!(dst, 0xA5A5_A5A5);
+        assert_eq!(writes, 3);
+    }
+
+    #[test]
+    fn critical_write_resets_when_write_doesnt_land() {
+        let fip = FaultInjectionPrevention::new();
+
+        assert!(resets(|| {
+            let mut dst: u32 = 0;
+            fip.critical_write(&mut dst, 1, |_, _| (), &mut TestRng(1));
+        }));
+        assert!(resets(|| {
+            let mut dst: u32 = 0;
+            fip.critical_write(&mut dst, 1, |dst, src| *dst = src ^ 0x100, &mut TestRng(1));
+        }));
+    }
+
+    #[test]
+    fn stack_canary_runs_body_with_canary_pushed() {
+        let fip = FaultInjectionPrevention::new();
+        let mut depths = [usize::MAX; 2];
+
+        fip.stack_canary(
+            || {
+                // SAFETY: The canary stack of this thread isn't borrowed.
+                depths[0] = unsafe { (*ref_canary()).counter };
+
+                fip.stack_canary(
+                    // SAFETY: Same as above.
+                    || depths[1] = unsafe { (*ref_canary()).counter },
+                    &mut TestRng(2),
+                );
+            },
+            &mut TestRng(1),
+        );
+
+        assert_eq!(depths, [1, 2]);
+        // SAFETY: Same as above.
+        assert_eq!(unsafe { (*ref_canary()).counter }, 0);
+    }
+
+    /// Canary RNG that returns a recognizable value.
+    fn constant_canary() -> u64 {
+        0xC0FF_EE00_C0FF_EE00
+    }
+
+    /// Runs `stack_canary()` and returns the canary the body runs with.
+    fn pushed_canary(fip: &FaultInjectionPrevention) -> u64 {
+        let mut canary = 0;
+
+        fip.stack_canary(
+            // SAFETY: The canary stack of this thread isn't borrowed.
+            || canary = unsafe { (*ref_canary()).peek() },
+            &mut TestRng(1),
+        );
+        canary
+    }
+
+    #[test]
+    fn stack_canary_uses_canary_rng_when_configured() {
+        let fip = FaultInjectionPrevention::new().with_canary_rng(constant_canary);
+
+        assert_eq!(pushed_canary(&fip), constant_canary());
+    }
+
+    #[test]
+    fn stack_canary_shares_rng_by_default() {
+        let fip = FaultInjectionPrevention::new();
+
+        assert_eq!(pushed_canary(&fip), TestRng(1).next_u64());
+    }
+
+    #[test]
+    fn stack_canary_resets_when_canary_is_overwritten() {
+        let fip = FaultInjectionPrevention::new();
+
+        assert!(resets(|| fip.stack_canary(
+            // SAFETY: The canary stack of this thread isn't borrowed.
+            || unsafe {
+                let stack = &mut *ref_canary();
+                stack.reference_canary_vec[stack.counter] ^= 1;
+            },
+            &mut TestRng(1),
+        )));
+    }
+
+    #[test]
+    fn stack_canary_repeats_comparison_for_redundancy() {
+        let delays = |redundancy| {
+            let fip = FaultInjectionPrevention::new().with_redundancy(redundancy);
+            let before = mock::delays();
+
+            fip.stack_canary(|| (), &mut TestRng(1));
+            mock::delays() - before
+        };
+
+        // Each comparison is a critical_if followed by a random delay.
+        assert_eq!(delays(0), delays(1));
+        assert_eq!(delays(3) - delays(1), 2 * 2);
+        assert_eq!(delays(8) - delays(1), 2 * 7);
+    }
+
+    #[test]
+    fn stack_canary_runs_documented_checks() {
+        let fip = FaultInjectionPrevention::new();
+        let before = mock::delays();
+
+        fip.stack_canary(|| (), &mut TestRng(1));
+
+        // 16 critical_ifs and the delay after the comparison, as documented in the README, plus
+        // the setup verification if it's enabled
+        let setup_checks = usize::from(cfg!(feature = "canary_setup_verify"));
+        assert_eq!(mock::delays() - before, 17 + setup_checks);
+    }
+
+    #[test]
+    fn canary_stack_resets_when_relocated() {
+        let fip = FaultInjectionPrevention::new();
+
+        // A canary stack at another address than the recorded base, like one reached through a
+        // corrupted pointer
+        let mut relocated = RefCanaryStack::new();
+        assert!(resets(|| relocated.push(0x1234, &fip, &mut TestRng(1))));
+
+        let mut relocated = RefCanaryStack::new();
+        relocated.counter = 1;
+        assert!(resets(|| {
+            relocated.pop(&fip, &mut TestRng(1));
+        }));
+
+        // The canary stack at the recorded base still works.
+        // SAFETY: The canary stack of this thread isn't borrowed.
+        unsafe {
+            (*ref_canary()).push(0x1234, &fip, &mut TestRng(1));
+            assert_eq!((*ref_canary()).pop(&fip, &mut TestRng(1)), 0x1234);
+        }
+    }
+
+    #[test]
+    fn stack_canary_catches_overwritten_canary_with_redundancy() {
+        for redundancy in [1, 2, 5] {
+            let fip = FaultInjectionPrevention::new().with_redundancy(redundancy);
+
+            assert!(resets(|| fip.stack_canary(
+                // SAFETY: The canary stack of this thread isn't borrowed.
+                || unsafe {
+                    let stack = &mut *ref_canary();
+                    stack.reference_canary_vec[stack.counter] ^= 1 << 63;
+                },
+                &mut TestRng(1),
+            )));
+        }
+    }
+
+    /// An RNG that overwrites the newest reference canary on every draw from `corrupt_from` on,
+    /// to inject corruption while a canary is being set up.
+    struct CorruptingRng<'a> {
+        inner: TestRng,
+        draws: &'a Cell<usize>,
+        corrupt_from: usize,
+    }
+
+    impl RngCore for CorruptingRng<'_> {
+        fn next_u32(&mut self) -> u32 {
+            // SAFETY: The canary stack of this thread isn't borrowed while an RNG draws.
+            let stack = unsafe { &mut *ref_canary() };
+
+            if self.draws.get() >= self.corrupt_from && stack.counter > 0 {
+                stack.reference_canary_vec[stack.counter] = 0xBAD;
+            }
+
+            self.draws.set(self.draws.get() + 1);
+            self.inner.next_u32()
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            (u64::from(self.next_u32()) << 32) | u64::from(self.next_u32())
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            self.inner.fill_bytes(dest);
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.inner.try_fill_bytes(dest)
+        }
+    }
+
+    impl rand_core::CryptoRng for CorruptingRng<'_> {}
+
+    /// Returns the number of RNG draws that `stack_canary` makes before it runs its body.
+    fn canary_setup_draws(fip: &FaultInjectionPrevention) -> usize {
+        let draws = Cell::new(0);
+        let draws_at_run = Cell::new(0);
+        let mut rng = CorruptingRng {
+            inner: TestRng(1),
+            draws: &draws,
+            corrupt_from: usize::MAX,
+        };
+
+        fip.stack_canary(|| draws_at_run.set(draws.get()), &mut rng);
+        draws_at_run.get()
+    }
+
+    #[cfg(feature = "canary_setup_verify")]
+    #[test]
+    fn stack_canary_catches_setup_corruption_before_run() {
+        let fip = FaultInjectionPrevention::new();
+
+        for corrupt_from in 0..canary_setup_draws(&fip) {
+            let ran = Cell::new(false);
+            let draws = Cell::new(0);
+            let mut rng = CorruptingRng {
+                inner: TestRng(1),
+                draws: &draws,
+                corrupt_from,
+            };
+
+            assert!(resets(|| fip.stack_canary(|| ran.set(true), &mut rng)));
+            assert!(!ran.get(), "corruption at draw {corrupt_from} reached run");
+
+            // SAFETY: The canary stack of this thread isn't borrowed.
+            unsafe { (*ref_canary()).counter = 0 };
+        }
+    }
+
+    #[cfg(not(feature = "canary_setup_verify"))]
+    #[test]
+    fn stack_canary_catches_setup_corruption_after_run() {
+        let fip = FaultInjectionPrevention::new();
+        let ran = Cell::new(false);
+        let draws = Cell::new(0);
+        let mut rng = CorruptingRng {
+            inner: TestRng(1),
+            draws: &draws,
+            corrupt_from: canary_setup_draws(&fip) - 1,
+        };
+
+        assert!(resets(|| fip.stack_canary(|| ran.set(true), &mut rng)));
+        assert!(ran.get());
+    }
+
+    #[cfg(feature = "panic_resets")]
+    #[test]
+    fn on_panic_resets_with_panic_resets() {
+        assert!(resets(|| on_panic()));
+        assert_eq!(mock::reset_requests(), 1);
+    }
+
+    #[cfg(not(feature = "panic_resets"))]
+    #[test]
+    fn on_panic_never_exits_without_panic_resets() {
+        // The mock never exits by unwinding like a reset, but without requesting one.
+        assert!(resets(|| on_panic()));
+        assert_eq!(mock::reset_requests(), 0);
+    }
+
+    #[test]
+    fn primitives_reset_when_config_is_corrupted() {
+        let mut fip = FaultInjectionPrevention::new();
+        // Corrupt a setting without updating the checksum, like a glitch would.
+        fip.redundancy = 0;
+        let called = Cell::new(false);
+        let call = || called.set(true);
+        let decoy = || ();
+
+        let primitives: [&dyn Fn(&mut TestRng); 8] = [
+            &|rng| fip.critical_if(|| SecureBool::True, call, || (), rng),
+            &|rng| fip.critical_for(1, |_| call(), rng),
+            &|rng| {
+                fip.critical_retry::<1>(
+                    || {
+                        call();
+                        SecureBool::True
+                    },
+                    rng,
+                );
+            },
+            &|rng| fip.run_random_decoy(call, &[&decoy], rng),
+            &|rng| fip.with_register_token(0x5A5A_A5A5, call, rng),
+            &|rng| {
+                fip.critical_mac_full(b"message", |_| call(), || [0; 16], rng);
+            },
+            &|rng| fip.critical_section(call, rng),
+            &|rng| {
+                let (mut scratch_a, mut scratch_b) = ([0u8; 4], [0u8; 4]);
+                fip.critical_redundant_effect(|_| call(), &mut scratch_a, &mut scratch_b, rng);
+            },
+        ];
+
+        for primitive in primitives {
+            assert!(resets(|| primitive(&mut TestRng(1))));
+            assert!(!called.get());
+        }
+    }
+
+    #[test]
+    fn with_sp_guard_passes_when_sp_is_restored() {
+        let fip = FaultInjectionPrevention::new();
+        let mut runs = 0;
+
+        mock::set_sp(0x2000_1000);
+        fip.with_sp_guard(
+            || {
+                // A balanced push and pop during the call
+                mock::set_sp(0x2000_0FF0);
+                mock::set_sp(0x2000_1000);
+                runs += 1;
+            },
+            &mut TestRng(1),
+        );
+        fip.stack_canary_with_sp_guard(|| runs += 1, &mut TestRng(1));
+
+        assert_eq!(runs, 2);
+    }
+
+    #[test]
+    fn with_sp_guard_resets_when_sp_is_corrupted() {
+        let fip = FaultInjectionPrevention::new();
+
+        mock::set_sp(0x2000_1000);
+        assert!(resets(|| {
+            fip.with_sp_guard(|| mock::set_sp(0x2000_0800), &mut TestRng(1));
+        }));
+
+        mock::set_sp(0x2000_1000);
+        assert!(resets(|| {
+            fip.stack_canary_with_sp_guard(|| mock::set_sp(0x2000_1004), &mut TestRng(1));
+        }));
+    }
+
+    /// Returns the depth of the canary stack of this thread.
+    fn canary_depth() -> usize {
+        // SAFETY: The canary stack of this thread isn't borrowed.
+        unsafe { (*ref_canary()).counter }
+    }
+
+    crate::critical_fn! {
+        fip, rng;
+        /// Reads `value` with the RNG passed to the body, and returns it with the canary depth.
+        fn canary_guarded(
+            fip: &FaultInjectionPrevention,
+            rng: &mut TestRng,
+            value: u32,
+        ) -> (u32, usize) {
+            if value == 0 {
+                return (0, canary_depth());
+            }
+
+            (fip.critical_read(&value, rng).into_inner(), canary_depth())
+        }
+    }
+
+    crate::critical_fn! {
+        fip, rng, sp_guard;
+        /// Moves SP to `sp` in the body, and returns the canary depth.
+        fn sp_guarded(fip: &FaultInjectionPrevention, rng: &mut TestRng, sp: usize) -> usize {
+            fip.critical_write(&mut 0u32, 1, |dst, src| *dst = src, rng);
+            mock::set_sp(sp);
+            canary_depth()
+        }
+    }
+
+    crate::critical_fn! {
+        fip, &mut TestRng(1);
+        /// Returns the canary depth, with an RNG expression that the body can't use.
+        fn expr_rng_guarded(fip: &FaultInjectionPrevention) -> usize {
+            canary_depth()
+        }
+    }
+
+    #[test]
+    fn critical_fn_runs_body_inside_canary_region() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        assert_eq!(canary_guarded(&fip, &mut rng, 0x1234), (0x1234, 1));
+        assert_eq!(canary_guarded(&fip, &mut rng, 0), (0, 1));
+        assert_eq!(expr_rng_guarded(&fip), 1);
+        assert_eq!(canary_depth(), 0);
+    }
+
+    #[test]
+    fn critical_fn_with_sp_guard_verifies_sp() {
+        let fip = FaultInjectionPrevention::new();
+
+        mock::set_sp(0x2000_1000);
+        assert_eq!(sp_guarded(&fip, &mut TestRng(1), 0x2000_1000), 1);
+        assert_eq!(canary_depth(), 0);
+
+        assert!(resets(|| {
+            sp_guarded(&fip, &mut TestRng(1), 0x2000_0800);
+        }));
+    }
+
+    #[test]
+    fn secure_reset_device_requests_reset() {
+        assert!(resets(|| FaultInjectionPrevention::secure_reset_device()));
+        assert_eq!(mock::reset_requests(), 1);
+    }
+
+    #[test]
+    fn secure_reset_device_retries_dropped_reset_request() {
+        mock::drop_reset_requests(1);
+
+        assert!(resets(|| FaultInjectionPrevention::secure_reset_device()));
+        assert_eq!(mock::reset_requests(), 2);
+    }
+
+    #[test]
+    fn secure_reset_device_never_exits_when_every_request_is_dropped() {
+        mock::drop_reset_requests(usize::MAX);
+
+        assert!(resets(|| FaultInjectionPrevention::secure_reset_device()));
+        assert_eq!(mock::reset_requests(), RESET_REQUEST_ATTEMPTS);
+    }
+}
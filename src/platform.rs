@@ -0,0 +1,299 @@
+//! Architecture-specific primitives used by the fault-injection protections. On ARM targets these
+//! are implemented with inline asm. On other targets, a host backend is used so the verification
+//! logic can be built and exercised on a host. Unit tests use a mock backend instead, whose state
+//! can be set by each test.
+
+#[cfg(test)]
+pub(crate) mod mock;
+
+#[cfg(target_arch = "arm")]
+use core::arch::asm;
+#[cfg(all(not(target_arch = "arm"), not(test)))]
+use core::sync::atomic::AtomicU32;
+#[cfg(not(test))]
+use core::sync::atomic::{compiler_fence, Ordering};
+
+// Application Interrupt and Reset Control Register
+#[cfg(target_arch = "arm")]
+const AIRCR_ADDR: u32 = 0xE000ED0C;
+#[cfg(target_arch = "arm")]
+const AIRCR_VECTKEY: u32 = 0x05FA << 16;
+#[cfg(target_arch = "arm")]
+const AIRCR_SYSRESETREQ: u32 = 1 << 2;
+
+/// Primitives that the fault-injection protections depend on.
+pub(crate) trait Platform {
+    /// Data synchronization barrier that is also a compiler fence.
+    fn dsb();
+
+    /// Busy-waits for at least `cycles` cycles.
+    fn delay(cycles: u32);
+
+    /// Requests a system reset. Execution may continue briefly until the reset takes effect.
+    fn request_reset();
+
+    /// Returns whether a system reset request has latched.
+    fn reset_requested() -> bool;
+
+    /// Never returns, even in cases of fault-injection attacks.
+    fn never_exit() -> !;
+
+    /// Reads the CONTROL register.
+    fn read_control() -> u32;
+
+    /// Reads the current stack pointer.
+    fn read_sp() -> usize;
+
+    /// Masks interrupts by setting PRIMASK.
+    fn disable_interrupts();
+
+    /// Unmasks interrupts by clearing PRIMASK.
+    fn enable_interrupts();
+
+    /// Reads the PRIMASK register.
+    fn read_primask() -> u32;
+
+    /// Reads the word at the 32-bit address `addr` with a volatile read, e.g. a memory-mapped
+    /// system register like SHCSR.
+    ///
+    /// # Safety
+    /// `addr` must be valid for volatile reads of a `u32` and properly aligned.
+    unsafe fn read_sys_reg(addr: u32) -> u32;
+
+    /// Reads the source of a redundant read with a volatile read.
+    ///
+    /// # Safety
+    /// Same as [`core::ptr::read_volatile()`].
+    #[inline(always)]
+    unsafe fn read_source<T: Copy>(src: *const T) -> T {
+        // SAFETY: The caller upholds the safety requirements of read_volatile.
+        unsafe { core::ptr::read_volatile(src) }
+    }
+
+    /// Loads `token` into the token register, calls `body`, and returns the value of the token
+    /// register after `body` returns.
+    fn run_with_token_register(token: u32, body: &mut dyn FnMut()) -> u32;
+}
+
+/// Calls the closure that `ctx` points to. Used as an AAPCS call target from inline asm.
+#[cfg(target_arch = "arm")]
+extern "C" fn call_token_body(ctx: *mut &mut dyn FnMut()) {
+    // SAFETY: ctx points to a live closure reference on the stack of run_with_token_register,
+    // which is blocked in the call until this returns.
+    unsafe { (*ctx)() }
+}
+
+/// ARMv7-M backend.
+#[cfg(target_arch = "arm")]
+pub(crate) struct CortexM;
+
+#[cfg(target_arch = "arm")]
+impl Platform for CortexM {
+    // https://github.com/rust-embedded/cortex-m/issues/308
+    #[inline(always)]
+    fn dsb() {
+        compiler_fence(Ordering::SeqCst);
+
+        // SAFETY: "dsb" is always safe.
+        unsafe { asm!("dsb") }
+
+        compiler_fence(Ordering::SeqCst);
+    }
+
+    #[inline(always)]
+    fn delay(cycles: u32) {
+        cortex_m::asm::delay(cycles);
+    }
+
+    #[inline(always)]
+    fn request_reset() {
+        // SAFETY: AIRCR_ADDR is a valid address for the AIRCR register, and is therefore properly
+        // aligned.
+        unsafe {
+            core::ptr::write_volatile(AIRCR_ADDR as *mut u32, AIRCR_VECTKEY | AIRCR_SYSRESETREQ);
+        }
+    }
+
+    #[inline(always)]
+    fn reset_requested() -> bool {
+        // SAFETY: AIRCR_ADDR is a valid address for the AIRCR register, and is therefore properly
+        // aligned.
+        unsafe { core::ptr::read_volatile(AIRCR_ADDR as *const u32) & AIRCR_SYSRESETREQ != 0 }
+    }
+
+    #[inline(always)]
+    fn never_exit() -> ! {
+        crate::never_exit!()
+    }
+
+    #[inline(always)]
+    fn read_control() -> u32 {
+        let control: u32;
+
+        // SAFETY: Reading CONTROL has no side effects.
+        unsafe {
+            asm!("mrs {}, CONTROL", out(reg) control, options(nomem, nostack, preserves_flags))
+        }
+
+        control
+    }
+
+    #[inline(always)]
+    fn read_sp() -> usize {
+        let sp: usize;
+
+        // SAFETY: Reading SP has no side effects.
+        unsafe { asm!("mov {}, sp", out(reg) sp, options(nomem, nostack, preserves_flags)) }
+
+        sp
+    }
+
+    #[inline(always)]
+    fn disable_interrupts() {
+        cortex_m::interrupt::disable();
+    }
+
+    #[inline(always)]
+    fn enable_interrupts() {
+        // SAFETY: Only called to restore interrupts that were enabled before they were disabled.
+        unsafe { cortex_m::interrupt::enable() }
+    }
+
+    #[inline(always)]
+    fn read_primask() -> u32 {
+        let primask: u32;
+
+        // SAFETY: Reading PRIMASK has no side effects.
+        unsafe {
+            asm!("mrs {}, PRIMASK", out(reg) primask, options(nomem, nostack, preserves_flags))
+        }
+
+        primask
+    }
+
+    #[inline(always)]
+    unsafe fn read_sys_reg(addr: u32) -> u32 {
+        // SAFETY: The caller guarantees addr is valid for volatile reads and properly aligned.
+        unsafe { core::ptr::read_volatile(addr as *const u32) }
+    }
+
+    #[inline(always)]
+    fn run_with_token_register(token: u32, mut body: &mut dyn FnMut()) -> u32 {
+        let observed: u32;
+
+        // SAFETY: The call target is an extern "C" function, and every register it may clobber
+        // under the AAPCS is marked as clobbered. r8 is callee-saved, so body preserves it, and it
+        // is declared as an output so the compiler doesn't keep anything in it across the asm.
+        unsafe {
+            asm!(
+                "mov r8, {token}",
+                "blx {call}",
+                "mov r0, r8",
+                token = in(reg) token,
+                call = in(reg) call_token_body as extern "C" fn(*mut &mut dyn FnMut()),
+                inlateout("r0") &mut body as *mut &mut dyn FnMut() => observed,
+                out("r8") _,
+                clobber_abi("C"),
+            )
+        }
+
+        observed
+    }
+}
+
+/// Host backend used when not building for an ARM target. A reset request panics, so a detected
+/// fault can be observed by the caller instead of hanging.
+#[cfg(all(not(target_arch = "arm"), not(test)))]
+pub(crate) struct Host;
+
+// Emulated PRIMASK of the host backend
+#[cfg(all(not(target_arch = "arm"), not(test)))]
+static HOST_PRIMASK: AtomicU32 = AtomicU32::new(0);
+
+// Emulated token register of the host backend
+#[cfg(all(not(target_arch = "arm"), not(test)))]
+static HOST_TOKEN_REGISTER: AtomicU32 = AtomicU32::new(0);
+
+#[cfg(all(not(target_arch = "arm"), not(test)))]
+impl Platform for Host {
+    #[inline(always)]
+    fn dsb() {
+        compiler_fence(Ordering::SeqCst);
+    }
+
+    #[inline(always)]
+    fn delay(cycles: u32) {
+        for _ in 0..cycles {
+            core::hint::spin_loop();
+        }
+    }
+
+    #[inline(never)]
+    fn request_reset() {
+        panic!("secure reset requested");
+    }
+
+    #[inline(always)]
+    fn reset_requested() -> bool {
+        true
+    }
+
+    #[inline(always)]
+    fn never_exit() -> ! {
+        loop {
+            core::hint::spin_loop();
+        }
+    }
+
+    #[inline(always)]
+    fn read_control() -> u32 {
+        // The host always runs privileged.
+        0
+    }
+
+    #[inline(always)]
+    fn read_sp() -> usize {
+        // The host has no portable way to read SP, so report a constant stack pointer.
+        0
+    }
+
+    #[inline(always)]
+    fn disable_interrupts() {
+        HOST_PRIMASK.store(1, Ordering::SeqCst);
+    }
+
+    #[inline(always)]
+    fn enable_interrupts() {
+        HOST_PRIMASK.store(0, Ordering::SeqCst);
+    }
+
+    #[inline(always)]
+    fn read_primask() -> u32 {
+        HOST_PRIMASK.load(Ordering::SeqCst)
+    }
+
+    #[inline(always)]
+    unsafe fn read_sys_reg(_addr: u32) -> u32 {
+        // The host has no system registers, so report them as cleared.
+        0
+    }
+
+    #[inline(always)]
+    fn run_with_token_register(token: u32, body: &mut dyn FnMut()) -> u32 {
+        HOST_TOKEN_REGISTER.store(token, Ordering::SeqCst);
+        body();
+        HOST_TOKEN_REGISTER.load(Ordering::SeqCst)
+    }
+}
+
+/// The backend for the current target.
+#[cfg(all(target_arch = "arm", not(test)))]
+pub(crate) type Arch = CortexM;
+
+/// The backend for the current target.
+#[cfg(all(not(target_arch = "arm"), not(test)))]
+pub(crate) type Arch = Host;
+
+/// The backend for unit tests.
+#[cfg(test)]
+pub(crate) type Arch = mock::Mock;
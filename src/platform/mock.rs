@@ -0,0 +1,211 @@
+//! Mock backend used by the unit tests. Its state is kept per thread, so tests running in parallel
+//! don't interfere with each other. A reset panics with [`RESET_PANIC`] instead of hanging, so a
+//! test can observe it with [`resets()`](crate::test_util::resets).
+
+use super::Platform;
+use core::sync::atomic::{compiler_fence, Ordering};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+/// Panic payload of a secure reset on the mock backend.
+pub(crate) const RESET_PANIC: &str = "secure reset requested";
+
+std::thread_local! {
+    // Emulated CONTROL register
+    static CONTROL: Cell<u32> = const { Cell::new(0) };
+
+    // Emulated stack pointer
+    static SP: Cell<usize> = const { Cell::new(0) };
+
+    // Emulated PRIMASK
+    static PRIMASK: Cell<u32> = const { Cell::new(0) };
+
+    // Whether interrupt disables have no effect, like a glitched cpsid
+    static DISABLES_IGNORED: Cell<bool> = const { Cell::new(false) };
+
+    // Emulated token register
+    static TOKEN_REGISTER: Cell<u32> = const { Cell::new(0) };
+
+    // Number of reset requests, and whether a reset request has latched
+    static RESET_REQUESTS: Cell<usize> = const { Cell::new(0) };
+    static RESET_LATCHED: Cell<bool> = const { Cell::new(false) };
+
+    // Number of upcoming reset requests that don't latch, like a glitched AIRCR write
+    static DROPPED_RESET_REQUESTS: Cell<usize> = const { Cell::new(0) };
+
+    // Emulated system registers and other words read by address. Words that were never set read
+    // as 0.
+    static SYS_REGS: RefCell<HashMap<u32, u32>> = RefCell::new(HashMap::new());
+
+    // Number of source reads until the source changes, if it will change
+    static READS_BEFORE_CHANGE: Cell<Option<usize>> = const { Cell::new(None) };
+
+    // Scripted values of registers that change between reads, by address. The last value
+    // repeats once the others have been read.
+    static SCRIPTED_READS: RefCell<HashMap<usize, Vec<u32>>> = RefCell::new(HashMap::new());
+
+    // Number of delays
+    static DELAYS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Sets the emulated CONTROL register of this thread.
+pub(crate) fn set_control(control: u32) {
+    CONTROL.set(control);
+}
+
+/// Sets the emulated stack pointer of this thread.
+pub(crate) fn set_sp(sp: usize) {
+    SP.set(sp);
+}
+
+/// Sets the emulated system register, or other word read by address, at `addr` of this thread.
+pub(crate) fn set_sys_reg(addr: u32, value: u32) {
+    SYS_REGS.with_borrow_mut(|regs| regs.insert(addr, value));
+}
+
+/// Returns the emulated token register of this thread.
+pub(crate) fn token_register() -> u32 {
+    TOKEN_REGISTER.get()
+}
+
+/// Overwrites the emulated token register of this thread, like a glitch on the register while a
+/// token is held in it.
+pub(crate) fn set_token_register(value: u32) {
+    TOKEN_REGISTER.set(value);
+}
+
+/// Makes interrupt disables on this thread have no effect, so PRIMASK still reports interrupts
+/// as enabled afterwards, like a glitched `cpsid`.
+pub(crate) fn ignore_interrupt_disables() {
+    DISABLES_IGNORED.set(true);
+}
+
+/// Changes the source of every redundant read on this thread after `reads` more reads, by flipping
+/// every bit of the values read from then on. Only for integer and raw pointer sources, for which
+/// every bit pattern is valid.
+pub(crate) fn change_source_after(reads: usize) {
+    READS_BEFORE_CHANGE.set(Some(reads));
+}
+
+/// Makes the redundant reads of the `u32` at `src` on this thread return `values` in order, like a
+/// register that changes between reads.
+pub(crate) fn script_reads(src: *const u32, values: &[u32]) {
+    assert!(!values.is_empty());
+
+    SCRIPTED_READS.with_borrow_mut(|reads| reads.insert(src as usize, values.to_vec()));
+}
+
+/// Makes the next `requests` reset requests on this thread not latch.
+pub(crate) fn drop_reset_requests(requests: usize) {
+    DROPPED_RESET_REQUESTS.set(requests);
+}
+
+/// Returns the number of reset requests on this thread.
+pub(crate) fn reset_requests() -> usize {
+    RESET_REQUESTS.get()
+}
+
+/// Returns the number of delays on this thread.
+pub(crate) fn delays() -> usize {
+    DELAYS.get()
+}
+
+/// Mock backend.
+pub(crate) struct Mock;
+
+impl Platform for Mock {
+    #[inline(always)]
+    fn dsb() {
+        compiler_fence(Ordering::SeqCst);
+    }
+
+    #[inline(always)]
+    fn delay(_cycles: u32) {
+        DELAYS.set(DELAYS.get() + 1);
+    }
+
+    fn request_reset() {
+        RESET_REQUESTS.set(RESET_REQUESTS.get() + 1);
+
+        match DROPPED_RESET_REQUESTS.get() {
+            0 => RESET_LATCHED.set(true),
+            dropped => DROPPED_RESET_REQUESTS.set(dropped - 1),
+        }
+    }
+
+    fn reset_requested() -> bool {
+        RESET_LATCHED.get()
+    }
+
+    fn never_exit() -> ! {
+        std::panic::panic_any(RESET_PANIC)
+    }
+
+    fn read_control() -> u32 {
+        CONTROL.get()
+    }
+
+    fn read_sp() -> usize {
+        SP.get()
+    }
+
+    fn disable_interrupts() {
+        if !DISABLES_IGNORED.get() {
+            PRIMASK.set(1);
+        }
+    }
+
+    fn enable_interrupts() {
+        PRIMASK.set(0);
+    }
+
+    fn read_primask() -> u32 {
+        PRIMASK.get()
+    }
+
+    unsafe fn read_sys_reg(addr: u32) -> u32 {
+        SYS_REGS.with_borrow(|regs| regs.get(&addr).copied().unwrap_or(0))
+    }
+
+    unsafe fn read_source<T: Copy>(src: *const T) -> T {
+        // SAFETY: The caller upholds the safety requirements of read_volatile.
+        let mut value = unsafe { core::ptr::read_volatile(src) };
+
+        if core::mem::size_of::<T>() == core::mem::size_of::<u32>() {
+            SCRIPTED_READS.with_borrow_mut(|reads| {
+                if let Some(values) = reads.get_mut(&(src as usize)) {
+                    let scripted = if values.len() > 1 {
+                        values.remove(0)
+                    } else {
+                        values[0]
+                    };
+
+                    // SAFETY: Only u32s are scripted, and T has the size of a u32.
+                    value = unsafe { core::mem::transmute_copy(&scripted) };
+                }
+            });
+        }
+
+        match READS_BEFORE_CHANGE.get() {
+            Some(0) => {
+                let bytes = core::ptr::addr_of_mut!(value).cast::<u8>();
+
+                for i in 0..core::mem::size_of::<T>() {
+                    // SAFETY: i is in bounds of value, and change_source_after() is only used
+                    // with integer and raw pointer sources, for which every bit pattern is valid.
+                    unsafe { *bytes.add(i) ^= 0xFF };
+                }
+            }
+            Some(reads) => READS_BEFORE_CHANGE.set(Some(reads - 1)),
+            None => {}
+        }
+
+        value
+    }
+
+    fn run_with_token_register(token: u32, body: &mut dyn FnMut()) -> u32 {
+        TOKEN_REGISTER.set(token);
+        body();
+        TOKEN_REGISTER.get()
+    }
+}
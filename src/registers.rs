@@ -0,0 +1,862 @@
+//! Fault-injection resistant verification of system and peripheral registers.
+
+use crate::platform::{Arch, Platform};
+use crate::{helper, FaultInjectionPrevention, SecureBool};
+use core::hint::black_box;
+use core::ptr::{read_volatile, write_volatile};
+use rand_core::CryptoRngCore;
+
+// CONTROL register nPRIV bit, set when thread mode is unprivileged
+const CONTROL_NPRIV: u32 = 1 << 0;
+
+// System Handler Control and State Register
+const SHCSR_ADDR: u32 = 0xE000ED24;
+const SHCSR_MEMFAULTENA: u32 = 1 << 16;
+const SHCSR_BUSFAULTENA: u32 = 1 << 17;
+const SHCSR_USGFAULTENA: u32 = 1 << 18;
+const SHCSR_ENABLE_MASK: u32 = SHCSR_MEMFAULTENA | SHCSR_BUSFAULTENA | SHCSR_USGFAULTENA;
+
+// Debug Halting Control and Status Register C_DEBUGEN bit, set when halting debug is enabled
+const DHCSR_C_DEBUGEN: u32 = 1 << 0;
+
+// SysTick Control and Status Register and Reload Value Register
+const SYST_CSR_ADDR: u32 = 0xE000E010;
+const SYST_RVR_ADDR: u32 = 0xE000E014;
+const SYST_CSR_ENABLE: u32 = 1 << 0;
+const SYST_RVR_RELOAD_MASK: u32 = 0x00FF_FFFF;
+
+// ARMv7-M Code region, where executable flash is mapped
+const CODE_REGION_START: usize = 0x0000_0000;
+const CODE_REGION_END: usize = 0x1FFF_FFFF;
+
+// Thumb bit of a function address
+const THUMB_BIT: usize = 1 << 0;
+
+// Maximum number of attempts to read a consistent pair of registers
+const READ_PAIR_ATTEMPTS: usize = 4;
+
+// Maximum number of polls of a crypto peripheral's status register before giving up on it
+// becoming ready
+const CRYPTO_READY_POLLS: u32 = 100_000;
+
+// Byte offset from an NVIC Interrupt Clear-Pending Register to the matching Interrupt Set-Pending
+// Register
+const NVIC_ICPR_TO_ISPR_OFFSET: usize = 0x80;
+
+impl FaultInjectionPrevention {
+    /// Verifies that the current privilege level matches `expect_privileged`. The CONTROL
+    /// register's nPRIV bit is read redundantly with inline asm, so a glitch that drops the
+    /// processor to unprivileged execution, or re-grants privilege, is detected.
+    ///
+    /// Returns [`SecureBool::True`] if the privilege level matches the expected one, and
+    /// [`SecureBool::False`] otherwise. If the redundant reads disagree, or `expect_privileged` is
+    /// [`SecureBool::Error`], the board securely resets itself.
+    ///
+    /// nPRIV only applies to thread mode. Handler mode is always privileged.
+    #[inline(always)]
+    pub fn critical_verify_privilege(
+        &self,
+        expect_privileged: SecureBool,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        if black_box(expect_privileged) == SecureBool::Error {
+            Self::secure_reset_device();
+        }
+
+        let control = self.critical_sample(Arch::read_control, rng);
+        let privileged = SecureBool::from(control & CONTROL_NPRIV == 0);
+
+        self.critical_eq(privileged, expect_privileged, rng)
+    }
+
+    /// Runs `body` with a security token held in a CPU register, and verifies that the register
+    /// still holds the token afterwards, catching glitches that corrupt register state across the
+    /// critical region, such as a skipped or corrupted register restore.
+    ///
+    /// On ARM, the token is held in r8. `body` is called through an `extern "C"` function with the
+    /// token already loaded, and r8 is read back when it returns. Since r8 is callee-saved under
+    /// the AAPCS, nothing has to be reserved globally: `body` may use r8 as long as it restores it,
+    /// which compiled code always does. This means the token may be spilled to the stack inside
+    /// `body`, so it shouldn't be a secret.
+    ///
+    /// If the register doesn't hold the token after `body`, the board securely resets itself.
+    #[inline(always)]
+    pub fn with_register_token(
+        &self,
+        token: u32,
+        body: impl FnOnce(),
+        rng: &mut impl CryptoRngCore,
+    ) {
+        self.verify_self();
+
+        let mut body = Some(body);
+        let mut call_body = || {
+            if let Some(body) = body.take() {
+                body();
+            }
+        };
+
+        helper::dsb();
+        let observed = black_box(Arch::run_with_token_register(
+            black_box(token),
+            &mut call_body,
+        ));
+        helper::dsb();
+
+        self.critical_assert(|| black_box(observed) == black_box(token), rng);
+    }
+
+    /// Checks whether an external tamper event has been latched, e.g. in an RTC-backed tamper
+    /// register. The latch register is read redundantly so a glitch on the read can't hide a
+    /// tamper event.
+    ///
+    /// Returns [`SecureBool::True`] if any of the bits in `tamper_mask` are set in the latch, and
+    /// [`SecureBool::False`] otherwise. If the redundant reads disagree, the board securely resets
+    /// itself.
+    ///
+    /// # Safety
+    /// `latch_reg` must be valid for volatile reads and properly aligned.
+    #[inline(always)]
+    pub unsafe fn critical_check_tamper_latch(
+        &self,
+        latch_reg: *const u32,
+        tamper_mask: u32,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        // SAFETY: The caller guarantees latch_reg is valid for volatile reads and properly
+        // aligned.
+        let latch = self.critical_sample(|| unsafe { read_volatile(latch_reg) }, rng);
+
+        self.critical_bool(
+            || (black_box(latch) & black_box(tamper_mask) != 0).into(),
+            rng,
+        )
+    }
+
+    /// Clears a latched tamper event and verifies that it was actually cleared. `clear_op` performs
+    /// the device-specific clear, e.g. writing `tamper_mask` to a write-one-to-clear register.
+    ///
+    /// If any of the bits in `tamper_mask` are still set in the latch after clearing, or the
+    /// redundant reads disagree, the board securely resets itself.
+    ///
+    /// # Safety
+    /// `latch_reg` must be valid for volatile reads and properly aligned.
+    #[inline(always)]
+    pub unsafe fn critical_clear_tamper_latch(
+        &self,
+        latch_reg: *const u32,
+        tamper_mask: u32,
+        clear_op: impl FnOnce(),
+        rng: &mut impl CryptoRngCore,
+    ) {
+        helper::dsb();
+        clear_op();
+        helper::dsb();
+
+        // SAFETY: The caller guarantees latch_reg is valid for volatile reads and properly
+        // aligned.
+        let tampered = unsafe { self.critical_check_tamper_latch(latch_reg, tamper_mask, rng) };
+
+        self.critical_assert(|| black_box(tampered) == SecureBool::False, rng);
+    }
+
+    /// Verifies that the fault handlers enabled in `expected_shcsr` are still enabled in SHCSR.
+    /// Secure firmware often enables the MemManage, BusFault, and UsageFault handlers to trap
+    /// attacks, and a glitch that disables them removes that trapping.
+    ///
+    /// Only the MEMFAULTENA, BUSFAULTENA, and USGFAULTENA bits are compared, since the active and
+    /// pending bits of SHCSR change during normal operation.
+    ///
+    /// Returns [`SecureBool::True`] if every expected fault handler is enabled, and
+    /// [`SecureBool::False`] otherwise. If the redundant reads disagree, the board securely resets
+    /// itself.
+    #[inline(always)]
+    pub fn critical_verify_fault_handlers(
+        &self,
+        expected_shcsr: u32,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        let expected = black_box(expected_shcsr & SHCSR_ENABLE_MASK);
+
+        // SAFETY: SHCSR_ADDR is the address of the SHCSR register.
+        let enabled = self.critical_sample(
+            || unsafe { Arch::read_sys_reg(SHCSR_ADDR) } & SHCSR_ENABLE_MASK,
+            rng,
+        );
+
+        self.critical_eq(enabled & expected, expected, rng)
+    }
+
+    /// Clears an interrupt pending bit and verifies that it stays clear, so a glitch that re-raises
+    /// the interrupt can't cause a spurious re-entry into a security-critical handler. `bit` is
+    /// cleared by writing it to `nvic_icpr`, then redundantly verified through the matching
+    /// Interrupt Set-Pending Register, which is located 0x80 bytes below `nvic_icpr`.
+    ///
+    /// If `bit` is out of range, the pending bit remains set, or the redundant reads disagree, the
+    /// board securely resets itself.
+    ///
+    /// # Safety
+    /// `nvic_icpr` must point to an NVIC Interrupt Clear-Pending Register, or memory with the same
+    /// layout, that is valid for volatile writes and properly aligned. The matching Interrupt
+    /// Set-Pending Register must be valid for volatile reads.
+    #[inline(always)]
+    pub unsafe fn critical_clear_pending(
+        &self,
+        nvic_icpr: *mut u32,
+        bit: u8,
+        rng: &mut impl CryptoRngCore,
+    ) {
+        self.critical_assert(|| black_box(bit) < 32, rng);
+
+        let mask = black_box(1u32 << bit);
+        let nvic_ispr = nvic_icpr
+            .cast::<u8>()
+            .wrapping_sub(NVIC_ICPR_TO_ISPR_OFFSET)
+            .cast::<u32>();
+
+        helper::dsb();
+
+        // SAFETY: The caller guarantees nvic_icpr is valid for volatile writes and properly
+        // aligned.
+        unsafe {
+            write_volatile(nvic_icpr, mask);
+        }
+
+        helper::dsb();
+
+        // SAFETY: The caller guarantees the matching ISPR is valid for volatile reads. It is
+        // properly aligned since nvic_icpr is properly aligned.
+        let pending = self.critical_sample(|| unsafe { read_volatile(nvic_ispr) } & mask, rng);
+
+        self.critical_assert(|| black_box(pending) == 0, rng);
+    }
+
+    /// Verifies that halting debug is disabled, so a glitch that re-enables the debug port in the
+    /// field is detected. The C_DEBUGEN bit of `dhcsr` is read redundantly.
+    ///
+    /// On ARMv7-M, pass the Debug Halting Control and Status Register (DHCSR) at `0xE000EDF0`.
+    /// C_DEBUGEN can only be set by a debugger through the DAP, so it stays clear while the debug
+    /// port is locked. The lock itself is vendor-specific, e.g. a readout protection level, and
+    /// should be verified separately with the vendor's registers.
+    ///
+    /// Returns [`SecureBool::True`] if halting debug is disabled, and [`SecureBool::False`]
+    /// otherwise. If the redundant reads disagree, the board securely resets itself.
+    ///
+    /// # Safety
+    /// `dhcsr` must be valid for volatile reads and properly aligned.
+    #[inline(always)]
+    pub unsafe fn critical_verify_debug_locked(
+        &self,
+        dhcsr: *const u32,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        // SAFETY: The caller guarantees dhcsr is valid for volatile reads and properly aligned.
+        let debug_enabled =
+            self.critical_sample(|| unsafe { read_volatile(dhcsr) } & DHCSR_C_DEBUGEN, rng);
+
+        self.critical_eq(debug_enabled, 0, rng)
+    }
+
+    /// Enables a cryptographic peripheral, such as a hardware AES accelerator, and confirms that it
+    /// is enabled and ready before it is used, so a glitch can't leave it running in an
+    /// uninitialized state. `enable_mask` is set in `enable_reg`, then `status_reg` is polled until
+    /// all of the `ready_mask` bits are set, and both registers are read back redundantly.
+    ///
+    /// Returns [`SecureBool::True`] if the peripheral is enabled and ready, and
+    /// [`SecureBool::False`] if it didn't become ready within 100,000 polls or the enable didn't
+    /// stick. If the redundant reads disagree, the board securely resets itself.
+    ///
+    /// # Safety
+    /// `enable_reg` must be valid for volatile reads and writes, `status_reg` must be valid for
+    /// volatile reads, and both must be properly aligned. Setting `enable_mask` in `enable_reg`
+    /// must be a valid operation for the peripheral.
+    #[inline(always)]
+    pub unsafe fn critical_enable_crypto(
+        &self,
+        enable_reg: *mut u32,
+        status_reg: *const u32,
+        enable_mask: u32,
+        ready_mask: u32,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        // SAFETY: The caller guarantees enable_reg is valid for volatile reads and writes and
+        // properly aligned, and that setting enable_mask is valid.
+        unsafe {
+            write_volatile(
+                enable_reg,
+                read_volatile(enable_reg) | black_box(enable_mask),
+            );
+        }
+
+        helper::dsb();
+
+        // SAFETY: The caller guarantees status_reg is valid for volatile reads and properly
+        // aligned.
+        let ready = self.critical_wait_for(
+            || unsafe { Arch::read_source(status_reg) } & ready_mask == ready_mask,
+            CRYPTO_READY_POLLS,
+            rng,
+        );
+
+        if black_box(ready) == SecureBool::False {
+            return SecureBool::False;
+        }
+
+        // Re-read both registers so a glitch on the wait above can't skip the enable.
+        // SAFETY: The caller guarantees both registers are valid for volatile reads and properly
+        // aligned.
+        self.critical_bool(
+            || unsafe {
+                (Arch::read_source(enable_reg) & enable_mask == black_box(enable_mask)
+                    && Arch::read_source(status_reg) & ready_mask == black_box(ready_mask))
+                .into()
+            },
+            rng,
+        )
+    }
+
+    /// Verifies that SysTick is enabled with the expected reload value, so a glitch that
+    /// reconfigures SysTick can't silently neuter timing defenses that depend on it. The SysTick
+    /// control and reload registers are read redundantly.
+    ///
+    /// Reading the control register clears its COUNTFLAG bit, so don't rely on COUNTFLAG across a
+    /// call to this function.
+    ///
+    /// Returns [`SecureBool::True`] if SysTick is enabled and its reload value matches the low 24
+    /// bits of `expected_reload`, and [`SecureBool::False`] otherwise. If the redundant reads
+    /// disagree, the board securely resets itself.
+    #[inline(always)]
+    pub fn critical_verify_systick(
+        &self,
+        expected_reload: u32,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        // SAFETY: SYST_CSR_ADDR and SYST_RVR_ADDR are the addresses of the SysTick registers.
+        let enabled = self.critical_sample(
+            || unsafe { Arch::read_sys_reg(SYST_CSR_ADDR) } & SYST_CSR_ENABLE,
+            rng,
+        );
+        let reload = self.critical_sample(
+            || unsafe { Arch::read_sys_reg(SYST_RVR_ADDR) } & SYST_RVR_RELOAD_MASK,
+            rng,
+        );
+
+        let expected_reload = black_box(expected_reload & SYST_RVR_RELOAD_MASK);
+
+        self.critical_bool(
+            || {
+                (black_box(enabled) == SYST_CSR_ENABLE && black_box(reload) == expected_reload)
+                    .into()
+            },
+            rng,
+        )
+    }
+
+    /// Validates a candidate vector table before jumping into it. The initial MSP (word 0) and
+    /// reset handler (word 1) of the table at `vtor` are read redundantly, then verified: the MSP
+    /// must be word-aligned and lie within `[ram_lo, ram_hi]`, and the reset handler must have the
+    /// Thumb bit set and lie within the ARMv7-M Code region (`0x00000000`-`0x1FFFFFFF`), where
+    /// flash is mapped. `ram_hi` is inclusive since the initial MSP is usually the end of RAM.
+    ///
+    /// Returns [`SecureBool::True`] if the vector table is valid, and [`SecureBool::False`]
+    /// otherwise. If the redundant reads disagree, the board securely resets itself.
+    ///
+    /// # Safety
+    /// `vtor` must be valid for volatile reads of two `u32` words and properly aligned.
+    #[inline(always)]
+    pub unsafe fn critical_validate_vector_table(
+        &self,
+        vtor: u32,
+        ram_lo: usize,
+        ram_hi: usize,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        // SAFETY: The caller guarantees vtor is valid for volatile reads of two words and properly
+        // aligned.
+        let initial_msp =
+            self.critical_sample(|| unsafe { Arch::read_sys_reg(vtor) }, rng) as usize;
+        let reset_handler = self
+            .critical_sample(|| unsafe { Arch::read_sys_reg(vtor.wrapping_add(4)) }, rng)
+            as usize;
+
+        self.critical_bool(
+            || {
+                let msp = black_box(initial_msp);
+                let handler = black_box(reset_handler);
+
+                (msp & 0b11 == 0
+                    && msp >= black_box(ram_lo)
+                    && msp <= black_box(ram_hi)
+                    && handler & THUMB_BIT == THUMB_BIT
+                    && (CODE_REGION_START..=CODE_REGION_END).contains(&handler))
+                .into()
+            },
+            rng,
+        )
+    }
+
+    /// Verifies that a security flag kept in RAM agrees with its mirror in a peripheral register,
+    /// e.g. a backup or scratch register, so a glitch that alters only one copy is detected. Both
+    /// locations are read redundantly.
+    ///
+    /// Returns [`SecureBool::True`] if both copies agree, and [`SecureBool::False`] otherwise. If
+    /// the redundant reads of either copy disagree, the board securely resets itself.
+    ///
+    /// # Safety
+    /// `reg` must be valid for volatile reads and properly aligned.
+    #[inline(always)]
+    pub unsafe fn critical_eq_mirror(
+        &self,
+        ram: &u32,
+        reg: *const u32,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        let ram_value = self.critical_read(ram, rng).into_inner();
+
+        // SAFETY: The caller guarantees reg is valid for volatile reads and properly aligned.
+        let reg_value = self.critical_sample(|| unsafe { read_volatile(reg) }, rng);
+
+        self.critical_eq(ram_value, reg_value, rng)
+    }
+
+    /// Reads a 64-bit value split across two 32-bit registers, e.g. a 64-bit timer, as a
+    /// consistent snapshot. Uses the standard technique of reading `hi`, `lo`, then `hi` again, and
+    /// retrying if `hi` changed because `lo` rolled over in between. The assembled value is then
+    /// verified redundantly against the stable pair.
+    ///
+    /// If a consistent pair can't be read within a bounded number of attempts, or a fault
+    /// injection is detected, the board securely resets itself.
+    ///
+    /// # Safety
+    /// `hi` and `lo` must be valid for volatile reads and properly aligned.
+    #[inline(always)]
+    pub unsafe fn critical_read_pair(
+        &self,
+        hi: *const u32,
+        lo: *const u32,
+        rng: &mut impl CryptoRngCore,
+    ) -> u64 {
+        let mut stable = None;
+
+        for _ in 0..black_box(READ_PAIR_ATTEMPTS) {
+            // SAFETY: The caller guarantees hi and lo are valid for volatile reads and properly
+            // aligned.
+            let (hi1, lo1, hi2) = unsafe {
+                let hi1 = Arch::read_source(hi);
+                helper::dsb();
+                let lo1 = Arch::read_source(lo);
+                helper::dsb();
+                (hi1, lo1, Arch::read_source(hi))
+            };
+
+            if black_box(hi1) == black_box(hi2) {
+                stable = Some((hi1, lo1, hi2));
+                break;
+            }
+        }
+
+        let Some((hi1, lo1, hi2)) = stable else {
+            Self::secure_reset_device();
+        };
+
+        let value = black_box((u64::from(hi1) << 32) | u64::from(lo1));
+
+        self.critical_assert(
+            || {
+                black_box(hi1) == black_box(hi2)
+                    && black_box(value) >> 32 == u64::from(hi2)
+                    && black_box(value) as u32 == lo1
+            },
+            rng,
+        );
+
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::mock;
+    use crate::test_util::{resets, TestRng};
+    use core::cell::Cell;
+
+    #[test]
+    fn critical_verify_privilege_when_privileged() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        mock::set_control(!CONTROL_NPRIV);
+
+        assert!(fip.critical_verify_privilege(SecureBool::True, &mut rng) == SecureBool::True);
+        assert!(fip.critical_verify_privilege(SecureBool::False, &mut rng) == SecureBool::False);
+    }
+
+    #[test]
+    fn critical_verify_privilege_when_unprivileged() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        mock::set_control(CONTROL_NPRIV);
+
+        assert!(fip.critical_verify_privilege(SecureBool::True, &mut rng) == SecureBool::False);
+        assert!(fip.critical_verify_privilege(SecureBool::False, &mut rng) == SecureBool::True);
+    }
+
+    #[test]
+    fn critical_verify_privilege_resets_on_error() {
+        let fip = FaultInjectionPrevention::new();
+
+        assert!(resets(|| {
+            fip.critical_verify_privilege(SecureBool::Error, &mut TestRng(1));
+        }));
+    }
+
+    #[test]
+    fn with_register_token_runs_body_with_token_loaded() {
+        let fip = FaultInjectionPrevention::new();
+        let seen = Cell::new(None);
+
+        fip.with_register_token(
+            0x5A5A_A5A5,
+            || seen.set(Some(mock::token_register())),
+            &mut TestRng(1),
+        );
+
+        assert_eq!(seen.get(), Some(0x5A5A_A5A5));
+    }
+
+    #[test]
+    fn with_register_token_passes_when_body_restores_register() {
+        let fip = FaultInjectionPrevention::new();
+
+        // The body uses the register, and restores it like compiled code would.
+        fip.with_register_token(
+            0x5A5A_A5A5,
+            || {
+                let saved = mock::token_register();
+                mock::set_token_register(0);
+                mock::set_token_register(saved);
+            },
+            &mut TestRng(1),
+        );
+    }
+
+    #[test]
+    fn with_register_token_resets_when_body_corrupts_register() {
+        let fip = FaultInjectionPrevention::new();
+
+        for corrupted in [0, 0x5A5A_A5A4, !0x5A5A_A5A5] {
+            assert!(resets(|| {
+                fip.with_register_token(
+                    0x5A5A_A5A5,
+                    || mock::set_token_register(corrupted),
+                    &mut TestRng(1),
+                );
+            }));
+        }
+    }
+
+    const CRYPTO_ENABLE: u32 = 1 << 0;
+    const CRYPTO_READY: u32 = (1 << 3) | (1 << 1);
+
+    /// Enables a crypto peripheral with simulated registers, and returns the result and the
+    /// enable register afterwards. The status register reads `status`, unless it's scripted.
+    fn enable_crypto(enable: &mut u32, status: &u32) -> SecureBool {
+        let fip = FaultInjectionPrevention::new();
+
+        // SAFETY: Both registers are valid, properly aligned u32s.
+        unsafe {
+            fip.critical_enable_crypto(enable, status, CRYPTO_ENABLE, CRYPTO_READY, &mut TestRng(1))
+        }
+    }
+
+    #[test]
+    fn critical_enable_crypto_enables_ready_peripheral() {
+        let mut enable = 0x100;
+
+        assert!(enable_crypto(&mut enable, &CRYPTO_READY) == SecureBool::True);
+        // The enable bit is set without touching the other bits.
+        assert_eq!(enable, 0x100 | CRYPTO_ENABLE);
+    }
+
+    #[test]
+    fn critical_enable_crypto_waits_for_ready() {
+        let mut enable = 0;
+        let status = 0;
+
+        mock::script_reads(&status, &[0, 0, CRYPTO_READY & (1 << 1), CRYPTO_READY]);
+        assert!(enable_crypto(&mut enable, &status) == SecureBool::True);
+    }
+
+    #[test]
+    fn critical_enable_crypto_fails_when_stuck_not_ready() {
+        let mut enable = 0;
+
+        assert!(enable_crypto(&mut enable, &0) == SecureBool::False);
+        assert!(enable_crypto(&mut enable, &(CRYPTO_READY & (1 << 3))) == SecureBool::False);
+    }
+
+    #[test]
+    fn critical_enable_crypto_fails_when_enable_doesnt_stick() {
+        let mut enable = 0;
+
+        // The peripheral ignores the write.
+        mock::script_reads(&enable, &[0]);
+        assert!(enable_crypto(&mut enable, &CRYPTO_READY) == SecureBool::False);
+    }
+
+    #[test]
+    fn critical_enable_crypto_resets_when_readback_disagrees() {
+        let mut enable = 0;
+        let status = 0;
+
+        // Ready for the poll, the 4 evaluations of the wait, and the first evaluation of the
+        // readback, then not ready
+        let mut script = [CRYPTO_READY; 7];
+        script[6] = 0;
+        mock::script_reads(&status, &script);
+        assert!(resets(|| {
+            enable_crypto(&mut enable, &status);
+        }));
+    }
+
+    #[test]
+    fn critical_check_tamper_latch_reports_latched_events() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        for (latch, tampered) in [
+            (0u32, SecureBool::False),
+            (0b0100, SecureBool::True),
+            (0b1000, SecureBool::False),
+        ] {
+            // SAFETY: latch is a valid u32.
+            let result = unsafe { fip.critical_check_tamper_latch(&latch, 0b0111, &mut rng) };
+            assert!(result == tampered);
+        }
+    }
+
+    #[test]
+    fn critical_clear_tamper_latch_verifies_clear() {
+        let fip = FaultInjectionPrevention::new();
+        let latch = Cell::new(0b0110u32);
+
+        // SAFETY: latch is a valid u32.
+        unsafe {
+            fip.critical_clear_tamper_latch(
+                latch.as_ptr(),
+                0b0110,
+                || latch.set(latch.get() & !0b0110),
+                &mut TestRng(1),
+            );
+        }
+
+        assert_eq!(latch.get(), 0);
+
+        // A clear that doesn't take effect.
+        latch.set(0b0110);
+        // SAFETY: Same as above.
+        assert!(resets(|| unsafe {
+            fip.critical_clear_tamper_latch(latch.as_ptr(), 0b0110, || (), &mut TestRng(1));
+        }));
+    }
+
+    #[test]
+    fn critical_verify_fault_handlers_with_mocked_shcsr() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+        // MEMFAULTACT and SVCALLPENDED are set, which must be ignored.
+        let status = (1 << 0) | (1 << 15);
+
+        for (shcsr, expected, enabled) in [
+            (SHCSR_ENABLE_MASK, SHCSR_ENABLE_MASK, SecureBool::True),
+            (
+                SHCSR_ENABLE_MASK | status,
+                SHCSR_ENABLE_MASK,
+                SecureBool::True,
+            ),
+            (
+                SHCSR_ENABLE_MASK,
+                SHCSR_ENABLE_MASK | status,
+                SecureBool::True,
+            ),
+            (SHCSR_USGFAULTENA, SHCSR_USGFAULTENA, SecureBool::True),
+            (
+                SHCSR_MEMFAULTENA | SHCSR_BUSFAULTENA,
+                SHCSR_ENABLE_MASK,
+                SecureBool::False,
+            ),
+            (status, SHCSR_MEMFAULTENA, SecureBool::False),
+            (0, 0, SecureBool::True),
+        ] {
+            mock::set_sys_reg(SHCSR_ADDR, shcsr);
+
+            assert!(fip.critical_verify_fault_handlers(expected, &mut rng) == enabled);
+        }
+    }
+
+    /// Simulated NVIC register pair: the ISPR at index 0, and the ICPR 0x80 bytes above it. Returns
+    /// the value written to the ICPR, or `None` if the device reset.
+    fn clear_pending(ispr: u32, bit: u8) -> Option<u32> {
+        let fip = FaultInjectionPrevention::new();
+        let mut nvic = [0u32; 1 + NVIC_ICPR_TO_ISPR_OFFSET / 4];
+        nvic[0] = ispr;
+        let icpr = nvic.as_mut_ptr().wrapping_add(NVIC_ICPR_TO_ISPR_OFFSET / 4);
+
+        // SAFETY: icpr and the ISPR below it are in bounds of nvic.
+        let reset = resets(|| unsafe { fip.critical_clear_pending(icpr, bit, &mut TestRng(1)) });
+
+        (!reset).then(|| nvic[nvic.len() - 1])
+    }
+
+    #[test]
+    fn critical_clear_pending_writes_clear_and_verifies_it() {
+        assert_eq!(clear_pending(0, 5), Some(1 << 5));
+        assert_eq!(clear_pending(!(1 << 31), 31), Some(1 << 31));
+    }
+
+    #[test]
+    fn critical_clear_pending_resets_when_bit_stays_pending() {
+        assert_eq!(clear_pending(1 << 5, 5), None);
+        assert_eq!(clear_pending(u32::MAX, 0), None);
+    }
+
+    #[test]
+    fn critical_clear_pending_resets_on_out_of_range_bit() {
+        assert_eq!(clear_pending(0, 32), None);
+    }
+
+    #[test]
+    fn critical_verify_debug_locked_with_mocked_dhcsr() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+        // S_HALT, S_REGRDY and C_MASKINTS are set, which must be ignored.
+        let status = (1 << 17) | (1 << 16) | (1 << 3);
+
+        for (dhcsr, locked) in [
+            (0u32, SecureBool::True),
+            (status, SecureBool::True),
+            (DHCSR_C_DEBUGEN, SecureBool::False),
+            (DHCSR_C_DEBUGEN | status, SecureBool::False),
+        ] {
+            // SAFETY: dhcsr is a valid u32.
+            let result = unsafe { fip.critical_verify_debug_locked(&dhcsr, &mut rng) };
+            assert!(result == locked);
+        }
+    }
+
+    #[test]
+    fn critical_verify_systick_with_mocked_registers() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+        // CLKSOURCE, TICKINT and COUNTFLAG are set, which must be ignored.
+        let status = (1 << 2) | (1 << 1) | (1 << 16);
+
+        for (csr, rvr, expected_reload, configured) in [
+            (SYST_CSR_ENABLE, 9_999, 9_999, SecureBool::True),
+            (SYST_CSR_ENABLE | status, 9_999, 9_999, SecureBool::True),
+            (
+                SYST_CSR_ENABLE,
+                SYST_RVR_RELOAD_MASK,
+                u32::MAX,
+                SecureBool::True,
+            ),
+            (0, 9_999, 9_999, SecureBool::False),
+            (status, 9_999, 9_999, SecureBool::False),
+            (SYST_CSR_ENABLE, 99, 9_999, SecureBool::False),
+        ] {
+            mock::set_sys_reg(SYST_CSR_ADDR, csr);
+            mock::set_sys_reg(SYST_RVR_ADDR, rvr);
+
+            assert!(fip.critical_verify_systick(expected_reload, &mut rng) == configured);
+        }
+    }
+
+    #[test]
+    fn critical_validate_vector_table_checks_msp_and_reset_handler() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+        let vtor = 0x0800_4000;
+        let (ram_lo, ram_hi) = (0x2000_0000, 0x2002_0000);
+
+        for (msp, handler, valid) in [
+            (0x2002_0000, 0x0800_4101, SecureBool::True),
+            (0x2000_0000, 0x0000_0401, SecureBool::True),
+            // MSP out of RAM, below and above
+            (0x1FFF_FFFC, 0x0800_4101, SecureBool::False),
+            (0x2002_0004, 0x0800_4101, SecureBool::False),
+            // Misaligned MSP
+            (0x2001_FFFE, 0x0800_4101, SecureBool::False),
+            // Reset handler without the Thumb bit
+            (0x2002_0000, 0x0800_4100, SecureBool::False),
+            // Reset handler outside the Code region, e.g. in RAM
+            (0x2002_0000, 0x2000_0101, SecureBool::False),
+            // Erased flash
+            (0xFFFF_FFFF, 0xFFFF_FFFF, SecureBool::False),
+        ] {
+            mock::set_sys_reg(vtor, msp);
+            mock::set_sys_reg(vtor + 4, handler);
+
+            // SAFETY: The vector table is emulated by the mock.
+            let result =
+                unsafe { fip.critical_validate_vector_table(vtor, ram_lo, ram_hi, &mut rng) };
+            assert!(result == valid);
+        }
+    }
+
+    #[test]
+    fn critical_eq_mirror_compares_ram_and_register() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+        let ram: u32 = 0xA5A5_5A5A;
+
+        for (reg, agree) in [
+            (0xA5A5_5A5Au32, SecureBool::True),
+            (0xA5A5_5A5B, SecureBool::False),
+            (0, SecureBool::False),
+        ] {
+            // SAFETY: reg is a valid u32 standing in for the scratch register.
+            let result = unsafe { fip.critical_eq_mirror(&ram, &reg, &mut rng) };
+            assert!(result == agree);
+        }
+    }
+
+    #[test]
+    fn critical_read_pair_reads_stable_pair() {
+        let fip = FaultInjectionPrevention::new();
+        let (hi, lo) = (0x0000_0001u32, 0x2345_6789u32);
+
+        // SAFETY: hi and lo are valid u32s.
+        let value = unsafe { fip.critical_read_pair(&hi, &lo, &mut TestRng(1)) };
+        assert_eq!(value, 0x0000_0001_2345_6789);
+    }
+
+    #[test]
+    fn critical_read_pair_retries_on_rollover() {
+        let fip = FaultInjectionPrevention::new();
+        let (hi, lo) = (0u32, 0u32);
+
+        // The low word rolls over between the first 2 reads of the high word.
+        mock::script_reads(&hi, &[0x0000_0007, 0x0000_0008]);
+        mock::script_reads(&lo, &[0xFFFF_FFFF, 0x0000_0002]);
+
+        // SAFETY: hi and lo are valid u32s.
+        let value = unsafe { fip.critical_read_pair(&hi, &lo, &mut TestRng(1)) };
+        assert_eq!(value, 0x0000_0008_0000_0002);
+    }
+
+    #[test]
+    fn critical_read_pair_resets_without_stable_pair() {
+        let fip = FaultInjectionPrevention::new();
+        let (hi, lo) = (0u32, 0u32);
+        // The high word changes on every read.
+        let his: Vec<u32> = (0..2 * READ_PAIR_ATTEMPTS as u32 + 1).collect();
+
+        mock::script_reads(&hi, &his);
+        assert!(resets(|| {
+            // SAFETY: hi and lo are valid u32s.
+            unsafe { fip.critical_read_pair(&hi, &lo, &mut TestRng(1)) };
+        }));
+    }
+}
@@ -0,0 +1,259 @@
+//! User-defined multi-state enums with large, mutually distant discriminants, generalizing
+//! [`SecureBool`](crate::SecureBool).
+
+use crate::FaultInjectionPrevention;
+use core::hint::black_box;
+use rand_core::CryptoRngCore;
+
+/// An enum generated by [`secure_enum`](macro@crate::secure_enum)!(), whose discriminants are large,
+/// mutually distant constants, so an attacker needs to flip many bits to turn one valid state into
+/// another.
+pub trait SecureEnum: Copy + Eq + 'static {
+    /// Every variant of the enum.
+    const VARIANTS: &'static [Self];
+
+    /// Reads the raw discriminant of the value with a volatile read, so a corrupted value that
+    /// isn't a valid variant can still be inspected.
+    fn raw(&self) -> usize;
+
+    /// Returns whether the raw discriminant of the value is exactly one of the valid variants.
+    /// Every variant is compared, without short-circuiting.
+    #[inline(always)]
+    fn is_valid(&self) -> bool {
+        let raw = black_box(self.raw());
+        let matches = Self::VARIANTS.iter().fold(0usize, |matches, variant| {
+            matches + usize::from(black_box(variant.raw()) == raw)
+        });
+
+        black_box(matches) == 1
+    }
+}
+
+/// Computes the discriminant of the variant at `index` of a [`SecureEnum`]. Every discriminant is
+/// `seed` XORed with a distinct Walsh codeword, so the discriminants of any two variants differ in
+/// exactly half of their bits, regardless of the random seed.
+#[doc(hidden)]
+pub const fn secure_enum_discriminant(seed: usize, index: u32) -> usize {
+    assert!(index < usize::BITS, "too many variants in secure enum");
+
+    let mut codeword: usize = 0;
+    let mut bit = 0;
+
+    while bit < usize::BITS {
+        if (index & bit).count_ones() % 2 == 1 {
+            codeword |= 1 << bit;
+        }
+
+        bit += 1;
+    }
+
+    seed ^ codeword
+}
+
+/// A macro for defining a multi-state enum with large, mutually distant discriminants, like
+/// [`SecureBool`](crate::SecureBool) but with user-defined states. The discriminants are derived
+/// from a random seed generated at compile time, and any two of them differ in half of their bits.
+/// Up to `usize::BITS` variants are supported. The generated enum implements [`SecureEnum`], so it
+/// can be used with [`FaultInjectionPrevention::critical_match()`], and with
+/// [`FaultInjectionPrevention::critical_if()`] by comparing against a variant.
+///
+/// ```ignore
+/// secure_enum! {
+///     /// Lifecycle state of the device.
+///     pub enum LockState {
+///         Locked,
+///         Unlocked,
+///         Provisioning,
+///         Error,
+///     }
+/// }
+///
+/// fip.critical_match(state, |state| match state {
+///     LockState::Unlocked => unlock(),
+///     _ => (),
+/// }, &mut rng);
+/// ```
+#[macro_export]
+macro_rules! secure_enum {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident {
+            $($variant:ident),+ $(,)?
+        }
+    ) => {
+        $crate::secure_enum!(@enum [$(#[$meta])*] $vis $name [$($variant),+] [$($variant),+]);
+    };
+    (@enum [$(#[$meta:meta])*] $vis:vis $name:ident [$($variant:ident),+] $all:tt) => {
+        $(#[$meta])*
+        #[derive(PartialEq, Eq, Clone, Copy)]
+        #[allow(clippy::enum_clike_unportable_variant)]
+        #[repr(usize)]
+        $vis enum $name {
+            $(
+                #[allow(missing_docs)]
+                $variant = $crate::__secure_enum_discriminant(
+                    $name::__SECURE_ENUM_SEED,
+                    $crate::secure_enum!(@index $variant $all),
+                ),
+            )+
+        }
+
+        impl $name {
+            const __SECURE_ENUM_SEED: usize = $crate::__const_random!(usize);
+        }
+
+        impl $crate::SecureEnum for $name {
+            const VARIANTS: &'static [Self] = &[$(Self::$variant),+];
+
+            #[inline(always)]
+            fn raw(&self) -> usize {
+                // SAFETY: The enum is repr(usize), so it can be read as a usize. self is non-null
+                // and properly aligned since it comes from a Rust reference.
+                unsafe {
+                    ::core::ptr::read_volatile(self as *const Self as *const usize)
+                }
+            }
+        }
+    };
+    (@index $variant:ident [$($all:ident),+]) => {{
+        #[allow(dead_code, non_camel_case_types, clippy::enum_variant_names)]
+        enum __SecureEnumIndex {
+            $($all),+
+        }
+
+        __SecureEnumIndex::$variant as u32
+    }};
+}
+
+impl FaultInjectionPrevention {
+    /// Runs `arm` with `value` after verifying that `value` is exactly one of the valid variants of
+    /// its [`SecureEnum`], so a glitched state that isn't a valid variant never reaches a `match`.
+    /// The raw discriminant is read redundantly, validated, and verified to be unchanged after
+    /// `arm` returns.
+    ///
+    /// If `value` isn't a valid variant or a fault injection is detected, the board securely
+    /// resets itself.
+    #[inline(always)]
+    pub fn critical_match<E: SecureEnum, R>(
+        &self,
+        value: E,
+        arm: impl FnOnce(E) -> R,
+        rng: &mut impl CryptoRngCore,
+    ) -> R {
+        let value = black_box(value);
+        let raw = self.critical_sample(|| value.raw(), rng);
+
+        self.critical_assert(|| value.is_valid(), rng);
+
+        let result = arm(value);
+
+        self.critical_assert(|| black_box(value.raw()) == black_box(raw), rng);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{resets, TestRng};
+
+    crate::secure_enum! {
+        /// Lifecycle state of a test device.
+        enum LockState {
+            Locked,
+            Unlocked,
+            Provisioning,
+            Error,
+        }
+    }
+
+    /// A raw discriminant, to drive `critical_match()` with values that aren't valid variants.
+    #[derive(PartialEq, Eq, Clone, Copy)]
+    struct RawState(usize);
+
+    impl SecureEnum for RawState {
+        const VARIANTS: &'static [Self] = &[
+            RawState(LockState::Locked as usize),
+            RawState(LockState::Unlocked as usize),
+        ];
+
+        fn raw(&self) -> usize {
+            self.0
+        }
+    }
+
+    #[test]
+    fn secure_enum_discriminants_are_mutually_distant() {
+        let variants = LockState::VARIANTS;
+
+        for (i, a) in variants.iter().enumerate() {
+            assert!(a.is_valid());
+
+            for b in &variants[i + 1..] {
+                assert_eq!((a.raw() ^ b.raw()).count_ones(), usize::BITS / 2);
+            }
+        }
+    }
+
+    #[test]
+    fn critical_match_runs_arm_with_each_variant() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        for (state, name) in [
+            (LockState::Locked, "locked"),
+            (LockState::Unlocked, "unlocked"),
+            (LockState::Provisioning, "provisioning"),
+            (LockState::Error, "error"),
+        ] {
+            let matched = fip.critical_match(
+                state,
+                |state| match state {
+                    LockState::Locked => "locked",
+                    LockState::Unlocked => "unlocked",
+                    LockState::Provisioning => "provisioning",
+                    LockState::Error => "error",
+                },
+                &mut rng,
+            );
+
+            assert_eq!(matched, name);
+        }
+    }
+
+    #[test]
+    fn critical_if_compares_against_variant() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        for (state, unlocked) in [(LockState::Unlocked, true), (LockState::Locked, false)] {
+            let mut ran_success = false;
+
+            fip.critical_if(
+                || (black_box(state) == LockState::Unlocked).into(),
+                || ran_success = true,
+                || (),
+                &mut rng,
+            );
+            assert_eq!(ran_success, unlocked);
+        }
+    }
+
+    #[test]
+    fn critical_match_resets_on_invalid_variant() {
+        let fip = FaultInjectionPrevention::new();
+        let locked = LockState::Locked as usize;
+
+        assert!(fip.critical_match(RawState(locked), |state| state.is_valid(), &mut TestRng(1)));
+
+        for raw in [locked ^ 1, LockState::Provisioning as usize, 0] {
+            let mut ran = false;
+
+            assert!(resets(|| {
+                fip.critical_match(RawState(raw), |_| ran = true, &mut TestRng(1));
+            }));
+            assert!(!ran);
+        }
+    }
+}
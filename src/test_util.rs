@@ -0,0 +1,58 @@
+//! Helpers shared by the unit tests.
+
+use crate::platform::mock::RESET_PANIC;
+use rand_core::{CryptoRng, Error, RngCore};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+/// Deterministic RNG so every test run sees the same delays and canaries.
+pub(crate) struct TestRng(pub(crate) u64);
+
+impl RngCore for TestRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        (u64::from(self.next_u32()) << 32) | u64::from(self.next_u32())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for byte in dest {
+            *byte = self.next_u32() as u8;
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for TestRng {}
+
+/// Runs `f` and returns whether it securely reset the device. Any other panic is propagated.
+pub(crate) fn resets(f: impl FnOnce()) -> bool {
+    static QUIET_RESETS: Once = Once::new();
+
+    // Expected resets would otherwise print a panic message each.
+    QUIET_RESETS.call_once(|| {
+        let default_hook = panic::take_hook();
+
+        panic::set_hook(Box::new(move |info| {
+            if info.payload().downcast_ref::<&str>() != Some(&RESET_PANIC) {
+                default_hook(info);
+            }
+        }));
+    });
+
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(()) => false,
+        Err(payload) if payload.downcast_ref::<&str>() == Some(&RESET_PANIC) => true,
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
@@ -0,0 +1,229 @@
+//! Unlocks that require multiple independent, verified steps.
+
+use crate::{FaultInjectionPrevention, SecureBool};
+use core::hint::black_box;
+use core::ptr::{read_volatile, write_volatile};
+use rand_core::CryptoRngCore;
+
+/// A high-value unlock that requires `N` independent steps, each gated by its own check, to all
+/// complete in order before it is granted, so a single glitch can't unlock it. Progress is stored
+/// redundantly: each step has its own [`SecureBool`], and the number of completed steps is stored
+/// alongside its complement.
+///
+/// ```ignore
+/// let mut unlock = UnlockSequence::<3>::new();
+///
+/// unlock.complete_step(0, || check_password(), &fip, &mut rng);
+/// unlock.complete_step(1, || check_token(), &fip, &mut rng);
+/// unlock.complete_step(2, || check_button(), &fip, &mut rng);
+///
+/// if unlock.is_unlocked(&fip, &mut rng) == SecureBool::True {
+///     // ...
+/// }
+/// ```
+pub struct UnlockSequence<const N: usize> {
+    steps: [SecureBool; N],
+    completed: usize,
+    completed_complement: usize,
+}
+
+impl<const N: usize> UnlockSequence<N> {
+    /// Creates a new unlock sequence with no completed steps.
+    pub const fn new() -> Self {
+        UnlockSequence {
+            steps: [SecureBool::False; N],
+            completed: 0,
+            completed_complement: !0,
+        }
+    }
+
+    /// Verifies that the redundant copies of the progress agree.
+    ///
+    /// If they disagree, the board securely resets itself.
+    #[inline(always)]
+    fn verify_progress(&self, fip: &FaultInjectionPrevention, rng: &mut impl CryptoRngCore) {
+        // SAFETY: The fields are non-null, properly aligned, and initialized since they come from
+        // a Rust reference.
+        fip.critical_assert(
+            || unsafe {
+                let completed = read_volatile(&self.completed);
+
+                completed == !read_volatile(&self.completed_complement)
+                    && completed <= N
+                    && self
+                        .steps
+                        .iter()
+                        .enumerate()
+                        .all(|(step, &done)| (done == SecureBool::True) == (step < completed))
+            },
+            rng,
+        );
+    }
+
+    /// Sets the number of completed steps and the state of every step to match.
+    #[inline(always)]
+    fn set_progress(&mut self, completed: usize) {
+        // SAFETY: The fields are non-null and properly aligned since they come from a Rust
+        // reference.
+        unsafe {
+            for (step, done) in self.steps.iter_mut().enumerate() {
+                write_volatile(done, SecureBool::from(step < completed));
+            }
+
+            write_volatile(&mut self.completed, completed);
+            write_volatile(&mut self.completed_complement, !completed);
+        }
+    }
+
+    /// Completes `step` if `check` passes and every earlier step has already been completed.
+    /// Completing a step out of order resets all progress, so the sequence has to be restarted.
+    ///
+    /// Returns [`SecureBool::True`] if the step was completed, and [`SecureBool::False`]
+    /// otherwise. If the stored progress was corrupted or a fault injection is detected, the board
+    /// securely resets itself.
+    #[inline(always)]
+    pub fn complete_step(
+        &mut self,
+        step: usize,
+        check: impl FnMut() -> SecureBool,
+        fip: &FaultInjectionPrevention,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        self.verify_progress(fip, rng);
+
+        // SAFETY: completed is non-null, properly aligned, and initialized since it comes from a
+        // Rust reference.
+        let in_order = fip.critical_bool(
+            || (black_box(step) == unsafe { read_volatile(&self.completed) } && step < N).into(),
+            rng,
+        );
+
+        if black_box(in_order) != SecureBool::True {
+            self.set_progress(0);
+            self.verify_progress(fip, rng);
+            return SecureBool::False;
+        }
+
+        let passed = fip.critical_bool(check, rng);
+
+        if black_box(passed) == SecureBool::True {
+            self.set_progress(step + 1);
+        }
+
+        self.verify_progress(fip, rng);
+        passed
+    }
+
+    /// Returns [`SecureBool::True`] if all `N` steps were verifiably completed in order, and
+    /// [`SecureBool::False`] otherwise. If the stored progress was corrupted or a fault injection
+    /// is detected, the board securely resets itself.
+    #[inline(always)]
+    pub fn is_unlocked(
+        &self,
+        fip: &FaultInjectionPrevention,
+        rng: &mut impl CryptoRngCore,
+    ) -> SecureBool {
+        self.verify_progress(fip, rng);
+
+        // SAFETY: The fields are non-null, properly aligned, and initialized since they come from
+        // a Rust reference.
+        fip.critical_bool(
+            || unsafe {
+                (read_volatile(&self.completed) == N
+                    && black_box(&self.steps)
+                        .iter()
+                        .all(|&done| done == SecureBool::True))
+                .into()
+            },
+            rng,
+        )
+    }
+}
+
+impl<const N: usize> Default for UnlockSequence<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{resets, TestRng};
+
+    const PASS: fn() -> SecureBool = || SecureBool::True;
+    const FAIL: fn() -> SecureBool = || SecureBool::False;
+
+    #[test]
+    fn unlock_sequence_unlocks_after_all_steps_in_order() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+        let mut unlock = UnlockSequence::<3>::new();
+
+        for step in 0..3 {
+            assert!(unlock.is_unlocked(&fip, &mut rng) == SecureBool::False);
+            assert!(unlock.complete_step(step, PASS, &fip, &mut rng) == SecureBool::True);
+        }
+
+        assert!(unlock.is_unlocked(&fip, &mut rng) == SecureBool::True);
+    }
+
+    #[test]
+    fn unlock_sequence_resets_progress_on_out_of_order_step() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+        let mut unlock = UnlockSequence::<3>::new();
+
+        unlock.complete_step(0, PASS, &fip, &mut rng);
+        unlock.complete_step(1, PASS, &fip, &mut rng);
+        // Repeating a step is out of order too.
+        assert!(unlock.complete_step(1, PASS, &fip, &mut rng) == SecureBool::False);
+        assert!(unlock.complete_step(2, PASS, &fip, &mut rng) == SecureBool::False);
+        assert!(unlock.is_unlocked(&fip, &mut rng) == SecureBool::False);
+
+        // The sequence has to be restarted.
+        for step in 0..3 {
+            assert!(unlock.complete_step(step, PASS, &fip, &mut rng) == SecureBool::True);
+        }
+        assert!(unlock.is_unlocked(&fip, &mut rng) == SecureBool::True);
+    }
+
+    #[test]
+    fn unlock_sequence_stays_locked_when_step_is_skipped_or_fails() {
+        let fip = FaultInjectionPrevention::new();
+        let mut rng = TestRng(1);
+
+        let mut skipped = UnlockSequence::<3>::new();
+        skipped.complete_step(0, PASS, &fip, &mut rng);
+        assert!(skipped.complete_step(2, PASS, &fip, &mut rng) == SecureBool::False);
+        assert!(skipped.is_unlocked(&fip, &mut rng) == SecureBool::False);
+
+        let mut failed = UnlockSequence::<3>::new();
+        failed.complete_step(0, PASS, &fip, &mut rng);
+        assert!(failed.complete_step(1, FAIL, &fip, &mut rng) == SecureBool::False);
+        assert!(failed.complete_step(2, PASS, &fip, &mut rng) == SecureBool::False);
+        assert!(failed.is_unlocked(&fip, &mut rng) == SecureBool::False);
+
+        let mut past_end = UnlockSequence::<3>::new();
+        assert!(past_end.complete_step(3, PASS, &fip, &mut rng) == SecureBool::False);
+    }
+
+    #[test]
+    fn unlock_sequence_resets_on_corrupted_progress() {
+        let fip = FaultInjectionPrevention::new();
+        let mut unlock = UnlockSequence::<3>::new();
+
+        // Glitch the count without its complement and step states.
+        unlock.completed = 3;
+        assert!(resets(|| {
+            unlock.is_unlocked(&fip, &mut TestRng(1));
+        }));
+
+        let mut unlock = UnlockSequence::<3>::new();
+        unlock.completed = 3;
+        unlock.completed_complement = !3;
+        assert!(resets(|| {
+            unlock.is_unlocked(&fip, &mut TestRng(1));
+        }));
+    }
+}
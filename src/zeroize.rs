@@ -0,0 +1,240 @@
+//! Fault-injection resistant zeroization of secrets.
+
+use crate::{helper, FaultInjectionPrevention, SecureBool};
+#[cfg(test)]
+use core::cell::UnsafeCell;
+use core::hint::black_box;
+#[cfg(not(test))]
+use core::ptr::addr_of_mut;
+use core::ptr::{null_mut, read_volatile, write_volatile};
+use rand_core::CryptoRngCore;
+
+// Maximum number of secrets that can be registered for zeroization on reset
+const SECRET_REGISTRY_CAPACITY: usize = 8;
+
+/// A secret region registered with [`FaultInjectionPrevention::register_secret()`].
+#[derive(Clone, Copy)]
+struct SecretRegion {
+    ptr: *mut u8,
+    len: usize,
+}
+
+/// Fixed-capacity registry of the secret regions zeroized on reset.
+struct SecretRegistry {
+    regions: [SecretRegion; SECRET_REGISTRY_CAPACITY],
+    count: usize,
+}
+
+impl SecretRegistry {
+    /// Creates an empty registry.
+    const fn new() -> Self {
+        SecretRegistry {
+            regions: [SecretRegion {
+                ptr: null_mut(),
+                len: 0,
+            }; SECRET_REGISTRY_CAPACITY],
+            count: 0,
+        }
+    }
+}
+
+#[cfg(not(test))]
+static mut SECRET_REGISTRY: SecretRegistry = SecretRegistry::new();
+
+// Unit tests run in parallel, so each test thread gets its own registry.
+#[cfg(test)]
+std::thread_local! {
+    static SECRET_REGISTRY: UnsafeCell<SecretRegistry> = const { UnsafeCell::new(SecretRegistry::new()) };
+}
+
+/// Returns a pointer to the global secret registry.
+#[cfg(not(test))]
+#[inline(always)]
+fn secret_registry() -> *mut SecretRegistry {
+    addr_of_mut!(SECRET_REGISTRY)
+}
+
+/// Returns a pointer to the secret registry of the current test thread.
+#[cfg(test)]
+#[inline(always)]
+fn secret_registry() -> *mut SecretRegistry {
+    SECRET_REGISTRY.with(UnsafeCell::get)
+}
+
+/// Zeroizes every registered secret region with volatile writes. Doesn't verify the writes, since
+/// it is only called right before a reset. Never inlined, so the loops aren't copied into every
+/// inlined reset path.
+#[inline(never)]
+pub(crate) fn wipe_registered_secrets() {
+    // SAFETY: No race conditions because this library only supports single threaded programs.
+    // SECRET_REGISTRY is a static, so it's non-null and properly aligned. The count is clamped so
+    // a corrupted count can't index out of bounds.
+    let registry = unsafe { &*secret_registry() };
+    let count = black_box(registry.count).min(SECRET_REGISTRY_CAPACITY);
+
+    for region in &registry.regions[..count] {
+        for i in 0..region.len {
+            // SAFETY: The caller of register_secret guaranteed that the region stays valid for
+            // writes of len bytes for the rest of the program.
+            unsafe {
+                write_volatile(region.ptr.add(i), 0);
+            }
+        }
+    }
+
+    helper::dsb();
+}
+
+impl FaultInjectionPrevention {
+    /// Registers a secret region, such as a key buffer, to be zeroized by
+    /// [`FaultInjectionPrevention::secure_reset_device()`] before every reset, so an attacker who
+    /// triggers a fault can't recover the secret with a cold-boot attack after the reset. Up to 8
+    /// regions can be registered.
+    ///
+    /// Returns [`SecureBool::True`] if the region was registered, and [`SecureBool::False`] if the
+    /// registry is full.
+    ///
+    /// # Safety
+    /// `secret` must be valid for writes of `len` bytes for the rest of the program.
+    pub unsafe fn register_secret(secret: *mut u8, len: usize) -> SecureBool {
+        // SAFETY: No race conditions because this library only supports single threaded programs.
+        // SECRET_REGISTRY is a static, so it's non-null and properly aligned.
+        let registry = unsafe { &mut *secret_registry() };
+
+        if registry.count >= SECRET_REGISTRY_CAPACITY {
+            return SecureBool::False;
+        }
+
+        registry.regions[registry.count] = SecretRegion { ptr: secret, len };
+        registry.count += 1;
+        SecureBool::True
+    }
+
+    /// Zeroizes `buf` with volatile writes, and verifies that the zeros landed by reading them
+    /// back through a separate `readback` aperture, such as a non-cached alias of the same RAM.
+    /// Reading back through `buf` itself could see stale data held in a cache. A DSB is issued
+    /// between the writes and the read-back.
+    ///
+    /// If any byte read through `readback` isn't zero, the board securely resets itself.
+    ///
+    /// # Safety
+    /// `readback` must be valid for volatile reads of `buf.len()` bytes.
+    #[inline(always)]
+    pub unsafe fn secure_zeroize_verified_through(
+        &self,
+        buf: &mut [u8],
+        readback: *const u8,
+        rng: &mut impl CryptoRngCore,
+    ) {
+        for byte in buf.iter_mut() {
+            // SAFETY: byte is non-null and properly aligned since it comes from a Rust reference.
+            unsafe {
+                write_volatile(byte, 0);
+            }
+        }
+
+        helper::dsb();
+
+        let len = black_box(buf.len());
+
+        // SAFETY: The caller guarantees that readback is valid for volatile reads of len bytes.
+        self.critical_assert(
+            || {
+                let mut residue: u8 = 0;
+
+                for i in 0..len {
+                    residue |= unsafe { read_volatile(black_box(readback).add(i)) };
+                }
+
+                black_box(residue) == 0
+            },
+            rng,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::mock;
+    use crate::test_util::{resets, TestRng};
+
+    /// Allocates a secret buffer that stays valid for the rest of the program, and registers it.
+    fn registered_secret(len: usize) -> &'static mut [u8] {
+        let secret = std::vec![0xA5; len].leak();
+
+        // SAFETY: secret is leaked, so it's valid for writes of len bytes for the rest of the
+        // program.
+        let registered =
+            unsafe { FaultInjectionPrevention::register_secret(secret.as_mut_ptr(), len) };
+        assert!(registered == SecureBool::True);
+        secret
+    }
+
+    #[test]
+    fn registered_secrets_are_zeroed_on_fault() {
+        let fip = FaultInjectionPrevention::new();
+        let key = registered_secret(32);
+        let nonce = registered_secret(12);
+        let unregistered = [0xA5u8; 8];
+
+        // The source changes between the redundant reads, so the fault resets the device.
+        mock::change_source_after(3);
+        assert!(resets(|| {
+            fip.critical_read(&0u32, &mut TestRng(1));
+        }));
+
+        assert!(key.iter().all(|&byte| byte == 0));
+        assert!(nonce.iter().all(|&byte| byte == 0));
+        assert_eq!(black_box(unregistered), [0xA5; 8]);
+    }
+
+    #[test]
+    fn register_secret_rejects_regions_when_full() {
+        for _ in 0..SECRET_REGISTRY_CAPACITY {
+            registered_secret(4);
+        }
+
+        let mut extra = [0xA5u8; 4];
+
+        // SAFETY: The registry is full, so extra isn't registered and is never written.
+        let registered =
+            unsafe { FaultInjectionPrevention::register_secret(extra.as_mut_ptr(), extra.len()) };
+        assert!(registered == SecureBool::False);
+    }
+
+    /// Zeroizes `buf` and verifies it through `backing`, and returns whether the device reset.
+    fn zeroize_through(buf: &mut [u8], backing: &[u8]) -> bool {
+        let fip = FaultInjectionPrevention::new();
+
+        resets(|| {
+            // SAFETY: backing is valid for reads of buf.len() bytes.
+            unsafe {
+                fip.secure_zeroize_verified_through(buf, backing.as_ptr(), &mut TestRng(1));
+            }
+        })
+    }
+
+    #[test]
+    fn secure_zeroize_verified_through_passes_when_backing_is_zeroed() {
+        let mut buf = [0xA5; 16];
+
+        // The writes reached the backing RAM, which is read through a separate aperture.
+        assert!(!zeroize_through(&mut buf, &[0; 16]));
+        assert_eq!(buf, [0; 16]);
+    }
+
+    #[test]
+    fn secure_zeroize_verified_through_resets_on_stale_backing() {
+        // The writes only reached a cache, so the backing RAM still holds the secret, even though
+        // reading back through buf would see zeros.
+        let mut buf = [0xA5; 16];
+        assert!(zeroize_through(&mut buf, &[0xA5; 16]));
+
+        // A single stale byte is enough.
+        let mut stale = [0; 16];
+        stale[15] = 0x01;
+        let mut buf = [0xA5; 16];
+        assert!(zeroize_through(&mut buf, &stale));
+    }
+}